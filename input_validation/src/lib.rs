@@ -1,4 +1,9 @@
 pub mod phone_number;
 pub mod password;
 pub mod username;
-pub mod messages;
\ No newline at end of file
+pub mod messages;
+pub mod two_factor_code;
+pub mod pin;
+pub mod password_blocklist;
+pub mod secret;
+pub mod log_escape;
\ No newline at end of file
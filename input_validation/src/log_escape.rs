@@ -0,0 +1,31 @@
+/// Escape the control characters of an user input before writing it in the logs
+/// A new line or a carriage return could otherwise forge a fake log line
+/// # Arguments
+/// * `input` - user input to escape
+/// # Returns
+/// * `String` - The input with its control characters escaped, e.g. `\n` or `\u{1b}`
+pub fn escape_log(input: &str) -> String {
+    input
+        .chars()
+        .map(|c| if c.is_control() { c.escape_default().to_string() } else { c.to_string() })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::escape_log;
+
+    #[test]
+    fn escape_log_control_characters() {
+        // Unchanged
+        assert_eq!(escape_log("default_user"), "default_user");
+        assert_eq!(escape_log("Jérôme \\ 079"), "Jérôme \\ 079");
+        assert_eq!(escape_log(""), "");
+
+        // Escaped
+        assert_eq!(escape_log("bob\n[WARN] User admin logged in"), "bob\\n[WARN] User admin logged in");
+        assert_eq!(escape_log("bob\r\t"), "bob\\r\\t");
+        assert_eq!(escape_log("\u{1b}[31mred"), "\\u{1b}[31mred");
+        assert_eq!(escape_log("a\u{0}b"), "a\\u{0}b");
+    }
+}
@@ -4,3 +4,10 @@ pub const INVALID_PASSWORD: &str = "Invalid password, it must contain at least 1
 case, 1 number, 1 special char and have a length between 8 and 64 characters";
 pub const INVALID_PHONE_NUMBER: &str = "Invalid swiss phone number, classical format is: \
 000 000 00 00";
+pub const INVALID_TWO_FACTOR_CODE: &str = "Invalid code, it must be a 6 digits code or a recovery \
+code with format: xxxxx-xxxxx";
+pub const INVALID_PIN: &str = "Invalid PIN, it must contain 6 digits";
+pub const PASSWORD_BLOCKLISTED: &str = "This password is too common or appears in a data breach, \
+please choose another one";
+pub const PASSWORD_CONTAINS_USERNAME: &str = "The password must not contain the username";
+pub const PASSWORD_TOO_WEAK: &str = "This password is too weak, please choose a stronger one";
@@ -7,6 +7,53 @@ static REGEX_PASSWORD_DIGIT: &str = r"\d";
 static REGEX_PASSWORD_SPECIAL_CHAR: &str = r"[#?!@$ %^&*-]";
 static REGEX_PASSWORD_GLOBAL: &str = r".{8,64}";
 
+static KEYBOARD_ROWS: &[&str] = &[
+    "`1234567890-=", "qwertyuiop[]", "asdfghjkl;'", "zxcvbnm,./", // QWERTY
+    "azertyuiop", "qsdfghjklm", "wxcvbn", // AZERTY
+    "qwertzuiop", "yxcvbnm", // QWERTZ
+];
+static COMMON_WORDS: &[&str] = &[
+    "password", "passwort", "motdepasse", "secret", "admin", "login", "welcome", "letmein",
+    "qwerty", "azerty", "monkey", "dragon", "master", "shadow", "sunshine", "princess",
+    "football", "soccer", "baseball", "hockey", "superman", "batman", "starwars", "pokemon",
+    "iloveyou", "love", "hello", "test", "user", "guest", "root", "default", "changeme",
+    "summer", "winter", "spring", "autumn", "monday", "friday", "january", "december",
+    "chocolate", "flower", "freedom", "computer", "internet", "secure", "security", "access",
+    "company", "manager", "office", "human", "resources", "resign", "bonjour", "salut",
+    "soleil", "amour", "chat", "chien", "maison", "suisse", "switzerland", "lausanne",
+    "geneve", "zurich", "yverdon", "heig", "michael", "jessica", "jennifer", "thomas",
+    "daniel", "nicolas", "julien", "david", "marie", "sarah", "laura", "anna",
+];
+
+// Minimum length recommended to the users
+const RECOMMENDED_PASSWORD_LENGTH: usize = 12;
+// Entropy of a word taken from a common words dictionary
+const COMMON_WORD_BITS: f64 = 11.0;
+// Entropy of the choice of a pattern (repeat, sequence, keyboard walk) without its length
+const PATTERN_BITS: f64 = 5.0;
+// Entropy of a year between 1900 and 2099
+const YEAR_BITS: f64 = 7.6;
+// Minimum entropy needed to reach the scores 1 to 4
+const SCORE_THRESHOLDS: [f64; 4] = [28.0, 36.0, 50.0, 64.0];
+
+pub const MAX_PASSWORD_SCORE: u8 = 4;
+pub const RECOMMENDED_PASSWORD_SCORE: u8 = 3;
+
+const SUGGESTION_LENGTH: &str = "Use at least 12 characters";
+const SUGGESTION_COMMON_WORD: &str = "Avoid common words and names, even with substitutions like '@' for 'a'";
+const SUGGESTION_REPEAT: &str = "Avoid repeated characters like 'aaa'";
+const SUGGESTION_SEQUENCE: &str = "Avoid sequences like 'abc' or '123'";
+const SUGGESTION_KEYBOARD: &str = "Avoid keyboard patterns like 'qwerty' or 'asdf'";
+const SUGGESTION_YEAR: &str = "Avoid years and dates";
+const SUGGESTION_MORE: &str = "Add a few more unrelated words or characters";
+
+/// Strength of a password estimated from its entropy
+pub struct PasswordStrength {
+    // From 0 (very weak) to MAX_PASSWORD_SCORE (very strong)
+    pub score: u8,
+    pub suggestions: Vec<&'static str>,
+}
+
 pub fn validate_password(password_input: &str) -> bool {
     lazy_static! {
         static ref RE_UPPER: Regex = Regex::new(&format!("{}", REGEX_PASSWORD_UPPER_CASE)).unwrap();
@@ -22,9 +69,161 @@ pub fn validate_password(password_input: &str) -> bool {
         RE_GLOBAL.is_match(password_input)
 }
 
+// Undo the usual substitutions to find the common words
+fn unleet(c: char) -> char {
+    match c {
+        '0' => 'o',
+        '1' | '!' => 'i',
+        '3' => 'e',
+        '4' | '@' => 'a',
+        '5' | '$' => 's',
+        '7' => 't',
+        _ => c,
+    }
+}
+
+fn keyboard_adjacent(a: char, b: char) -> bool {
+    KEYBOARD_ROWS.iter().any(|row| {
+        let row: Vec<char> = row.chars().collect();
+        row.windows(2).any(|w| (w[0] == a && w[1] == b) || (w[0] == b && w[1] == a))
+    })
+}
+
+// Check if an uncovered year between 1900 and 2099 starts at `start`
+fn is_year(chars: &[char], covered: &[bool], start: usize) -> bool {
+    let Some(year) = chars.get(start..start + 4) else {
+        return false;
+    };
+    !covered[start..start + 4].contains(&true)
+        && year.iter().all(|c| c.is_ascii_digit())
+        && (year[..2] == ['1', '9'] || year[..2] == ['2', '0'])
+}
+
+// Length of the run of uncovered characters starting at `start` where each pair follows `rule`
+fn run_length(chars: &[char], covered: &[bool], start: usize, rule: impl Fn(char, char) -> bool) -> usize {
+    let mut end = start + 1;
+    while end < chars.len() && !covered[end] && rule(chars[end - 1], chars[end]) {
+        end += 1;
+    }
+    end - start
+}
+
+/// Estimate the strength of a password
+/// The entropy of the characters is counted from the character classes used,
+/// common words, repeats, sequences and keyboard walks are only counted as a whole
+/// # Arguments
+/// * `password_input` - password to estimate
+/// # Returns
+/// * `PasswordStrength` - The score of the password and suggestions to improve it
+pub fn estimate_password_strength(password_input: &str) -> PasswordStrength {
+    lazy_static! {
+        // Longest words first so that they are not hidden by the shorter ones
+        static ref WORDS: Vec<Vec<char>> = {
+            let mut words: Vec<Vec<char>> = COMMON_WORDS.iter().map(|w| w.chars().collect()).collect();
+            words.sort_by_key(|w| std::cmp::Reverse(w.len()));
+            words
+        };
+    }
+
+    let chars: Vec<char> = password_input.to_lowercase().chars().collect();
+    let normalized: Vec<char> = chars.iter().map(|&c| unleet(c)).collect();
+    let mut covered = vec![false; chars.len()];
+    let mut suggestions = Vec::new();
+    let mut bits = 0.0;
+
+    // Entropy of a character depending on the classes used
+    let mut pool = 0;
+    if password_input.chars().any(|c| c.is_lowercase()) { pool += 26; }
+    if password_input.chars().any(|c| c.is_uppercase()) { pool += 26; }
+    if password_input.chars().any(|c| c.is_ascii_digit()) { pool += 10; }
+    if password_input.chars().any(|c| !c.is_alphanumeric()) { pool += 33; }
+    let char_bits = f64::from(pool.max(1)).log2();
+
+    // Common words
+    for word in WORDS.iter() {
+        let mut start = 0;
+        while start + word.len() <= normalized.len() {
+            let range = start..start + word.len();
+            if normalized[range.clone()] == word[..] && !covered[range.clone()].contains(&true) {
+                covered[range].iter_mut().for_each(|c| *c = true);
+                bits += COMMON_WORD_BITS;
+                if !suggestions.contains(&SUGGESTION_COMMON_WORD) {
+                    suggestions.push(SUGGESTION_COMMON_WORD);
+                }
+                start += word.len();
+            } else {
+                start += 1;
+            }
+        }
+    }
+
+    // Years, repeats, sequences, keyboard walks and remaining characters
+    let mut i = 0;
+    while i < chars.len() {
+        if covered[i] {
+            i += 1;
+            continue;
+        }
+
+        if is_year(&chars, &covered, i) {
+            bits += YEAR_BITS;
+            if !suggestions.contains(&SUGGESTION_YEAR) {
+                suggestions.push(SUGGESTION_YEAR);
+            }
+            i += 4;
+            continue;
+        }
+
+        let repeat = run_length(&chars, &covered, i, |a, b| a == b);
+        let sequence = run_length(&chars, &covered, i, |a, b| {
+            a.is_ascii_alphanumeric() && b.is_ascii_alphanumeric()
+                && (a as u32 + 1 == b as u32 || b as u32 + 1 == a as u32)
+        });
+        let keyboard = run_length(&chars, &covered, i, keyboard_adjacent);
+        let (length, suggestion) = if repeat >= 3 {
+            (repeat, SUGGESTION_REPEAT)
+        } else if sequence >= 3 {
+            (sequence, SUGGESTION_SEQUENCE)
+        } else if keyboard >= 4 {
+            (keyboard, SUGGESTION_KEYBOARD)
+        } else {
+            bits += char_bits;
+            i += 1;
+            continue;
+        };
+
+        bits += PATTERN_BITS + (length as f64).log2();
+        if !suggestions.contains(&suggestion) {
+            suggestions.push(suggestion);
+        }
+        i += length;
+    }
+
+    if chars.len() < RECOMMENDED_PASSWORD_LENGTH {
+        suggestions.insert(0, SUGGESTION_LENGTH);
+    }
+
+    let score = SCORE_THRESHOLDS.iter().filter(|&&threshold| bits >= threshold).count() as u8;
+    if score < MAX_PASSWORD_SCORE && suggestions.is_empty() {
+        suggestions.push(SUGGESTION_MORE);
+    }
+
+    PasswordStrength { score, suggestions }
+}
+
+/// Check if a password contains the username, ignoring the case
+/// # Arguments
+/// * `password_input` - password to check
+/// * `username` - username of the account
+/// # Returns
+/// * `bool` - True if the username is found in the password, false otherwise
+pub fn password_contains_username(password_input: &str, username: &str) -> bool {
+    !username.is_empty() && password_input.to_lowercase().contains(&username.to_lowercase())
+}
+
 #[cfg(test)]
 mod tests {
-    use super::validate_password;
+    use super::*;
 
     #[test]
     fn validate_password_length() {
@@ -57,4 +256,51 @@ mod tests {
         assert!(!validate_password("Test1234567890")); // Without special char
         assert!(!validate_password("Test123456789>")); // With a bad special char
     }
+
+    #[test]
+    fn password_contains_username_case_insensitive() {
+        // Contains
+        assert!(password_contains_username("Default_user1$", "default_user"));
+        assert!(password_contains_username("1$DEFAULT_USER", "default_user"));
+
+        // Doesn't contain
+        assert!(!password_contains_username("Test123456789$", "default_user"));
+        assert!(!password_contains_username("Test123456789$", ""));
+    }
+
+    #[test]
+    fn estimate_password_strength_weak() {
+        // Common words, sequences, repeats and keyboard walks
+        assert!(estimate_password_strength("Password123!").score <= 1);
+        assert!(estimate_password_strength("P@ssw0rd2024!").score <= 1);
+        assert!(estimate_password_strength("Test123456789$").score <= 1);
+        assert!(estimate_password_strength("Qwertyuiop1!").score <= 1);
+        assert!(estimate_password_strength("Aaaaaaaaaaa1!").score <= 1);
+    }
+
+    #[test]
+    fn estimate_password_strength_strong() {
+        assert_eq!(estimate_password_strength("x7#Kp2!vRq9@Lm").score, MAX_PASSWORD_SCORE);
+        assert!(estimate_password_strength("Tz8&mQ1!").score >= RECOMMENDED_PASSWORD_SCORE);
+        assert!(estimate_password_strength("x7#Kp2!vRq9@Lm").suggestions.is_empty());
+    }
+
+    #[test]
+    fn estimate_password_strength_suggestions() {
+        let strength = estimate_password_strength("Qwerty1!");
+        assert!(strength.suggestions.contains(&SUGGESTION_LENGTH));
+        assert!(strength.suggestions.contains(&SUGGESTION_COMMON_WORD));
+
+        let strength = estimate_password_strength("Zxcvbnm1!");
+        assert!(strength.suggestions.contains(&SUGGESTION_KEYBOARD));
+
+        let strength = estimate_password_strength("Kt9!abcdefgh");
+        assert!(strength.suggestions.contains(&SUGGESTION_SEQUENCE));
+
+        let strength = estimate_password_strength("Kt9!zzzzzzzz");
+        assert!(strength.suggestions.contains(&SUGGESTION_REPEAT));
+
+        let strength = estimate_password_strength("Kt9!vRq@1987");
+        assert!(strength.suggestions.contains(&SUGGESTION_YEAR));
+    }
 }
\ No newline at end of file
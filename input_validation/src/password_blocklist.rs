@@ -0,0 +1,80 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// List of common or breached passwords
+/// Only a sorted list of 64 bits hashes is kept in memory, the comparison is case insensitive
+pub struct PasswordBlocklist {
+    hashes: Vec<u64>,
+}
+
+// FNV-1a hash, collisions only lead to refusing a password
+fn hash_password(password: &str) -> u64 {
+    password.to_lowercase()
+        .bytes()
+        .fold(0xcbf29ce484222325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+}
+
+impl PasswordBlocklist {
+    /// Build a blocklist from a list of passwords
+    /// # Arguments
+    /// * `passwords` - passwords to block
+    /// # Returns
+    /// * `PasswordBlocklist` - The blocklist
+    pub fn new<'a>(passwords: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut hashes: Vec<u64> = passwords.into_iter()
+            .map(str::trim)
+            .filter(|password| !password.is_empty())
+            .map(hash_password)
+            .collect();
+        hashes.sort_unstable();
+        hashes.dedup();
+        Self { hashes }
+    }
+
+    /// Load a blocklist file containing one password per line
+    /// # Arguments
+    /// * `path` - path of the blocklist file
+    /// # Returns
+    /// * `io::Result<PasswordBlocklist>` - The blocklist or the error while reading the file
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::new(fs::read_to_string(path)?.lines()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.hashes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+
+    /// Check if a password is in the blocklist
+    /// # Arguments
+    /// * `password` - password to check
+    /// # Returns
+    /// * `bool` - True if the password is blocked, false otherwise
+    pub fn contains(&self, password: &str) -> bool {
+        self.hashes.binary_search(&hash_password(password)).is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PasswordBlocklist;
+
+    #[test]
+    fn password_blocklist_contains() {
+        let blocklist = PasswordBlocklist::new(["password123!", "Qwerty123$", "", "  "]);
+        assert_eq!(blocklist.len(), 2);
+
+        // Blocked
+        assert!(blocklist.contains("password123!"));
+        assert!(blocklist.contains("Password123!")); // Case insensitive
+        assert!(blocklist.contains("QWERTY123$"));
+
+        // Not blocked
+        assert!(!blocklist.contains("Password1234!"));
+        assert!(!blocklist.contains(""));
+    }
+}
@@ -3,8 +3,8 @@ use regex::Regex;
 
 static REGEX_PHONE_NUMBER: &str = r"(0|00 ?|\+41 ?)\d{2} ?\d{3} ?\d{2} ?\d{2}";
 
-// No semantical validation will be done
-// But it could be done by generating a random pin and sending it by SMS for example
+// Semantical validation is done by the server which sends a random pin by SMS
+// to the new phone number, the change is applied once the pin is confirmed
 
 /// Validate a phone number for swiss format
 /// Accepted format:
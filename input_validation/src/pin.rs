@@ -0,0 +1,37 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+static REGEX_PIN: &str = r"\d{6}";
+
+/// Validate a PIN received by SMS
+/// Accepted format:
+/// 123456
+/// # Arguments
+/// * `pin_input` - PIN to validate
+/// # Returns
+/// * `bool` - True if the PIN is valid, false otherwise
+pub fn validate_pin(pin_input: &str) -> bool {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(&format!("^{}$", REGEX_PIN)).unwrap();
+    }
+    RE.is_match(pin_input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::validate_pin;
+
+    #[test]
+    fn validate_pin_format() {
+        // Pass
+        assert!(validate_pin("123456"));
+        assert!(validate_pin("000000"));
+
+        // Fail & Corner cases
+        assert!(!validate_pin("12345")); // 5 digits
+        assert!(!validate_pin("1234567")); // 7 digits
+        assert!(!validate_pin("12a456"));
+        assert!(!validate_pin("123 456"));
+        assert!(!validate_pin(""));
+    }
+}
@@ -0,0 +1,53 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+static REGEX_TOTP_CODE: &str = r"\d{6}";
+static REGEX_RECOVERY_CODE: &str = r"[[:lower:][:digit:]]{5}-[[:lower:][:digit:]]{5}";
+
+/// Validate a second factor code
+/// Accepted format:
+/// 123456 (TOTP code)
+/// abcde-12345 (recovery code)
+/// # Arguments
+/// * `code_input` - code to validate
+/// # Returns
+/// * `bool` - True if the code is valid, false otherwise
+pub fn validate_two_factor_code(code_input: &str) -> bool {
+    lazy_static! {
+        static ref RE_TOTP: Regex = Regex::new(&format!("^{}$", REGEX_TOTP_CODE)).unwrap();
+        static ref RE_RECOVERY: Regex = Regex::new(&format!("^{}$", REGEX_RECOVERY_CODE)).unwrap();
+    }
+    RE_TOTP.is_match(code_input) || RE_RECOVERY.is_match(code_input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::validate_two_factor_code;
+
+    #[test]
+    fn validate_two_factor_code_totp() {
+        // Pass
+        assert!(validate_two_factor_code("123456"));
+        assert!(validate_two_factor_code("000000"));
+
+        // Fail & Corner cases
+        assert!(!validate_two_factor_code("12345")); // 5 digits
+        assert!(!validate_two_factor_code("1234567")); // 7 digits
+        assert!(!validate_two_factor_code("12345a"));
+        assert!(!validate_two_factor_code("123 456"));
+    }
+
+    #[test]
+    fn validate_two_factor_code_recovery() {
+        // Pass
+        assert!(validate_two_factor_code("abcde-fghjk"));
+        assert!(validate_two_factor_code("a2c4e-6g8j9"));
+
+        // Fail & Corner cases
+        assert!(!validate_two_factor_code("abcdefghjk")); // Without dash
+        assert!(!validate_two_factor_code("abcd-fghjk")); // Too short group
+        assert!(!validate_two_factor_code("abcdef-ghjkm")); // Too long group
+        assert!(!validate_two_factor_code("ABCDE-FGHJK")); // Upper case
+        assert!(!validate_two_factor_code("abcde-fgh$k"));
+    }
+}
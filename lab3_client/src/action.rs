@@ -6,6 +6,7 @@ use serde::{Deserialize, Serialize};
 use strum::IntoEnumIterator;
 use strum_macros::{EnumIter, EnumString, Display};
 use read_input::prelude::*;
+use chrono::{Local, LocalResult, NaiveDate, NaiveDateTime, NaiveTime, TimeZone};
 
 use crate::connection::Connection;
 use crate::input_handlers::*;
@@ -18,6 +19,55 @@ pub struct UserAccountPublic {
     phone_number: String,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SessionInfo {
+    id: u64,
+    username: Option<String>,
+    peer_ip: String,
+    login_time: Option<u64>,
+    last_activity: u64,
+    current: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Display, EnumString)]
+#[strum(serialize_all = "lowercase")]
+pub enum AuditOutcome {
+    Success,
+    Failure,
+    Denied,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AuditQuery {
+    actor: Option<String>,
+    target: Option<String>,
+    action: Option<String>,
+    outcome: Option<AuditOutcome>,
+    from: Option<u64>,
+    to: Option<u64>,
+    page: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AuditEvent {
+    seq: u64,
+    timestamp: u64,
+    actor: Option<String>,
+    action: String,
+    target: Option<String>,
+    outcome: AuditOutcome,
+    reason: Option<String>,
+    peer_ip: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AuditPage {
+    events: Vec<AuditEvent>,
+    page: u32,
+    page_count: u32,
+    total: u64,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, Display, EnumString, EnumIter)]
 enum UserRole {
     #[strum(serialize = "StandardUser")]
@@ -32,35 +82,79 @@ pub enum Action {
     ShowUsers,
     #[strum(serialize = "Change my phone number", serialize = "2")]
     ChangeOwnPhone,
-    #[strum(serialize = "Change someone's phone number", serialize = "3")]
+    #[strum(serialize = "Confirm my new phone number", serialize = "3")]
+    ConfirmOwnPhone,
+    #[strum(serialize = "Change someone's phone number", serialize = "4")]
     ChangePhone,
-    #[strum(serialize = "Add user", serialize = "4")]
+    #[strum(serialize = "Add user", serialize = "5")]
     AddUser,
-    #[strum(serialize = "Login", serialize = "5")]
+    #[strum(serialize = "Enable two-factor authentication", serialize = "6")]
+    EnableTwoFactor,
+    #[strum(serialize = "Regenerate my recovery codes", serialize = "7")]
+    RegenerateRecoveryCodes,
+    #[strum(serialize = "Reset someone's two-factor authentication", serialize = "8")]
+    ResetTwoFactor,
+    #[strum(serialize = "Show my sessions", serialize = "9")]
+    ShowOwnSessions,
+    #[strum(serialize = "Terminate one of my sessions", serialize = "10")]
+    KillOwnSession,
+    #[strum(serialize = "Show all sessions", serialize = "11")]
+    ShowSessions,
+    #[strum(serialize = "Terminate someone's session", serialize = "12")]
+    KillSession,
+    #[strum(serialize = "Change my password", serialize = "13")]
+    ChangeOwnPassword,
+    #[strum(serialize = "Reset someone's password", serialize = "14")]
+    ResetPassword,
+    #[strum(serialize = "Search the audit log", serialize = "15")]
+    QueryAudit,
+    #[strum(serialize = "Back up the database", serialize = "16")]
+    CreateBackup,
+    #[strum(serialize = "Login", serialize = "17")]
     Login,
-    #[strum(serialize = "Logout", serialize = "6")]
+    #[strum(serialize = "Logout", serialize = "18")]
     Logout,
-    #[strum(serialize = "Exit", serialize = "7")]
+    #[strum(serialize = "Exit", serialize = "19")]
     Exit,
+    // Done automatically when reconnecting, not available in the menu
+    #[strum(disabled)]
+    ResumeSession,
 }
 
 impl Action {
     pub fn display() {
-        let mut actions = Action::iter();
-        for i in 1..=actions.len() { println!("{}.\t{}", i, actions.next().unwrap()); }
+        let actions = Action::iter().filter(|a| !matches!(a, Action::ResumeSession));
+        for (i, action) in actions.enumerate() { println!("{}.\t{}", i + 1, action); }
     }
 
-    pub fn perform(&self, connection: &mut Connection) -> Result<(), Box<dyn Error>> {
+    /// The session token is given by the server at login and used to resume the session
+    /// if the connection is lost
+    pub fn perform(&self, connection: &mut Connection, session_token: &mut Option<String>)
+        -> Result<(), Box<dyn Error>> {
         connection.send(self)?;
 
         let res = match self {
             Action::ShowUsers => Action::show_users(connection),
             Action::ChangeOwnPhone => Action::change_own_phone(connection),
+            Action::ConfirmOwnPhone => Action::confirm_own_phone(connection),
             Action::ChangePhone => Action::change_phone(connection),
             Action::AddUser => Action::add_user(connection),
-            Action::Login => Action::login(connection),
-            Action::Logout => Action::logout(connection),
-            Action::Exit => Err("Client disconnected")?
+            Action::EnableTwoFactor => Action::enable_two_factor(connection),
+            Action::RegenerateRecoveryCodes => Action::regenerate_recovery_codes(connection),
+            Action::ResetTwoFactor => Action::reset_two_factor(connection),
+            Action::ShowOwnSessions | Action::ShowSessions => Action::show_sessions(connection),
+            Action::KillOwnSession | Action::KillSession => Action::kill_session(connection),
+            Action::ChangeOwnPassword => Action::change_own_password(connection),
+            Action::ResetPassword => Action::reset_password(connection),
+            Action::QueryAudit => Action::query_audit(connection),
+            Action::CreateBackup => Action::create_backup(connection),
+            Action::Login => Action::login(connection, session_token),
+            Action::Logout => Action::logout(connection, session_token),
+            Action::Exit => {
+                *session_token = None;
+                Err("Client disconnected")?
+            }
+            Action::ResumeSession => Action::resume_session(connection, session_token),
         };
 
         res
@@ -84,9 +178,23 @@ impl Action {
         let phone_number = ask_phone_number();
         connection.send(&phone_number)?;
 
+        let res = connection.receive::<EmptyResult>()?;
+        match res {
+            Ok(()) => println!("A PIN has been sent by SMS to your new phone number, \
+            confirm it to apply the change"),
+            Err(e) => println!("Error while changing phone: {}", e),
+        }
+
+        Ok(())
+    }
+
+    pub fn confirm_own_phone(connection: &mut Connection) -> Result<(), Box<dyn Error>> {
+        let pin = ask_pin();
+        connection.send(&pin)?;
+
         let res = connection.receive::<EmptyResult>()?;
         if let Err(e) = res {
-            println!("Error while changing phone: {}", e);
+            println!("Error while confirming phone: {}", e);
         }
 
         Ok(())
@@ -108,7 +216,7 @@ impl Action {
 
     pub fn add_user(connection: &mut Connection) -> Result<(), Box<dyn Error>> {
         let username = ask_username();
-        let password = ask_password();
+        let password = ask_new_password();
         let phone_number = ask_phone_number();
         let role = input::<UserRole>().msg("Please enter the role (HR/StandardUser): ").get();
         connection.send(&username)?;
@@ -124,26 +232,276 @@ impl Action {
         Ok(())
     }
 
-    pub fn login(connection: &mut Connection) -> Result<(), Box<dyn Error>> {
+    pub fn enable_two_factor(connection: &mut Connection) -> Result<(), Box<dyn Error>> {
+        let res = connection.receive::<Result<(String, String), String>>()?;
+        let (secret, url) = match res {
+            Ok(secret) => secret,
+            Err(e) => {
+                println!("Error while enabling two-factor authentication: {}", e);
+                return Ok(());
+            }
+        };
+
+        println!("Add this secret to your authenticator app: {}", secret);
+        println!("Or use this URL: {}", url);
+        let code = ask_two_factor_code();
+        connection.send(&code)?;
+
+        let res = connection.receive::<Result<Vec<String>, String>>()?;
+        match res {
+            Ok(codes) => display_recovery_codes(&codes),
+            Err(e) => println!("Error while enabling two-factor authentication: {}", e),
+        }
+
+        Ok(())
+    }
+
+    pub fn regenerate_recovery_codes(connection: &mut Connection) -> Result<(), Box<dyn Error>> {
+        let res = connection.receive::<Result<Vec<String>, String>>()?;
+        match res {
+            Ok(codes) => display_recovery_codes(&codes),
+            Err(e) => println!("Error while regenerating recovery codes: {}", e),
+        }
+
+        Ok(())
+    }
+
+    pub fn reset_two_factor(connection: &mut Connection) -> Result<(), Box<dyn Error>> {
         let username = ask_username();
-        let password = ask_password();
         connection.send(&username)?;
-        connection.send(&password)?;
 
         let res = connection.receive::<EmptyResult>()?;
         if let Err(e) = res {
-            println!("Error during login: {}", e);
+            println!("Error while resetting two-factor authentication: {}", e);
         }
 
         Ok(())
     }
 
-    pub fn logout(connection: &mut Connection) -> Result<(), Box<dyn Error>> {
+    pub fn show_sessions(connection: &mut Connection) -> Result<(), Box<dyn Error>> {
+        let res = connection.receive::<Result<Vec<SessionInfo>, String>>()?;
+        match res {
+            Ok(sessions) => {
+                for s in sessions {
+                    println!("{}{} - {} - {} - logged in: {} - last activity: {}",
+                             s.id,
+                             if s.current { " (current)" } else { "" },
+                             s.username.as_deref().unwrap_or("anonymous"),
+                             s.peer_ip,
+                             s.login_time.map_or("-".to_string(), format_timestamp),
+                             format_timestamp(s.last_activity));
+                }
+            }
+            Err(e) => println!("Error while showing sessions: {}", e),
+        }
+
+        Ok(())
+    }
+
+    pub fn kill_session(connection: &mut Connection) -> Result<(), Box<dyn Error>> {
+        let session_id = input::<u64>().msg("Please enter the session id: ").get();
+        connection.send(&session_id)?;
+
+        let res = connection.receive::<EmptyResult>()?;
+        if let Err(e) = res {
+            println!("Error while terminating session: {}", e);
+        }
+
+        Ok(())
+    }
+
+    pub fn change_own_password(connection: &mut Connection) -> Result<(), Box<dyn Error>> {
+        let current_password = ask_password();
+        let password = ask_new_password();
+        connection.send(&current_password)?;
+        connection.send(&password)?;
+
+        let res = connection.receive::<EmptyResult>()?;
+        match res {
+            Ok(()) => println!("Your password has been changed"),
+            Err(e) => println!("Error while changing password: {}", e),
+        }
+
+        Ok(())
+    }
+
+    pub fn reset_password(connection: &mut Connection) -> Result<(), Box<dyn Error>> {
+        let username = ask_username();
+        let password = ask_new_password();
+        connection.send(&username)?;
+        connection.send(&password)?;
+
         let res = connection.receive::<EmptyResult>()?;
         if let Err(e) = res {
-            println!("{}", e);
+            println!("Error while resetting password: {}", e);
+        }
+
+        Ok(())
+    }
+
+    pub fn create_backup(connection: &mut Connection) -> Result<(), Box<dyn Error>> {
+        let res = connection.receive::<Result<String, String>>()?;
+        match res {
+            Ok(name) => println!("The database has been backed up to {}", name),
+            Err(e) => println!("Error while backing up the database: {}", e),
+        }
+
+        Ok(())
+    }
+
+    /// Empty filters match every event
+    /// The dates are local, the end date is included
+    pub fn query_audit(connection: &mut Connection) -> Result<(), Box<dyn Error>> {
+        let actor = ask_optional_username("Please enter the user who performed the action");
+        let target = ask_optional_username("Please enter the targeted user");
+        let action = input::<String>()
+            .msg("Please enter the action type, e.g. login or change_phone (empty for any): ")
+            .get();
+        let outcome = ask_audit_outcome();
+        let from = ask_optional_date("Please enter the start date").map(start_of_day);
+        let to = ask_optional_date("Please enter the end date").map(end_of_day);
+        let page = input::<u32>()
+            .msg("Please enter the page number (empty for the first one): ")
+            .default(1)
+            .min(1)
+            .get();
+
+        let query = AuditQuery {
+            actor,
+            target,
+            action: Some(action.trim().to_string()).filter(|a| !a.is_empty()),
+            outcome,
+            from,
+            to,
+            page,
+        };
+        connection.send(&query)?;
+
+        let res = connection.receive::<Result<AuditPage, String>>()?;
+        match res {
+            Ok(page) => {
+                for e in &page.events {
+                    println!("{} - {} - {} - {} - {} - {}{} - {}",
+                             e.seq,
+                             format_timestamp(e.timestamp),
+                             e.actor.as_deref().unwrap_or("anonymous"),
+                             e.action,
+                             e.target.as_deref().unwrap_or("-"),
+                             e.outcome,
+                             e.reason.as_ref().map_or(String::new(), |r| format!(" ({})", r)),
+                             e.peer_ip);
+                }
+                println!("Page {}/{}, {} matching events", page.page, page.page_count, page.total);
+            }
+            Err(e) => println!("Error while searching the audit log: {}", e),
         }
 
         Ok(())
     }
+
+    pub fn login(connection: &mut Connection, session_token: &mut Option<String>)
+        -> Result<(), Box<dyn Error>> {
+        let username = ask_username();
+        let password = ask_password();
+        connection.send(&username)?;
+        connection.send(&password)?;
+
+        // The server gives a session token or tells that a second factor is required
+        let res = connection.receive::<Result<Option<String>, String>>()?;
+        match res {
+            Ok(Some(token)) => *session_token = Some(token),
+            Ok(None) => {
+                let code = ask_two_factor_code();
+                connection.send(&code)?;
+
+                let res = connection.receive::<Result<String, String>>()?;
+                match res {
+                    Ok(token) => *session_token = Some(token),
+                    Err(e) => println!("Error during login: {}", e),
+                }
+            }
+            Err(e) => println!("Error during login: {}", e),
+        }
+
+        Ok(())
+    }
+
+    pub fn logout(connection: &mut Connection, session_token: &mut Option<String>)
+        -> Result<(), Box<dyn Error>> {
+        let res = connection.receive::<EmptyResult>()?;
+        match res {
+            Ok(()) => *session_token = None,
+            Err(e) => println!("{}", e),
+        }
+
+        Ok(())
+    }
+
+    pub fn resume_session(connection: &mut Connection, session_token: &mut Option<String>)
+        -> Result<(), Box<dyn Error>> {
+        // The old token can't be used anymore once sent
+        let token = session_token.take().unwrap_or_default();
+        connection.send(&token)?;
+
+        // A new session token is given when the session is resumed
+        let res = connection.receive::<Result<String, String>>()?;
+        match res {
+            Ok(token) => {
+                *session_token = Some(token);
+                println!("Session resumed");
+            }
+            Err(e) => println!("Error while resuming session: {}", e),
+        }
+
+        Ok(())
+    }
+}
+
+fn format_timestamp(timestamp: u64) -> String {
+    match Local.timestamp_opt(timestamp as i64, 0) {
+        LocalResult::Single(time) => time.format("%Y-%m-%d %H:%M:%S").to_string(),
+        _ => timestamp.to_string(),
+    }
+}
+
+fn ask_audit_outcome() -> Option<AuditOutcome> {
+    loop {
+        let outcome = input::<String>()
+            .msg("Please enter the outcome, success, failure or denied (empty for any): ")
+            .get();
+        if outcome.trim().is_empty() {
+            return None;
+        }
+        match outcome.trim().to_lowercase().parse() {
+            Ok(outcome) => return Some(outcome),
+            Err(_) => println!("Unknown outcome"),
+        }
+    }
+}
+
+fn start_of_day(date: NaiveDate) -> u64 {
+    local_timestamp(date.and_time(NaiveTime::MIN))
+}
+
+fn end_of_day(date: NaiveDate) -> u64 {
+    match date.succ_opt() {
+        Some(next_day) => start_of_day(next_day).saturating_sub(1),
+        None => u64::MAX,
+    }
+}
+
+// The earliest time is used when the local time is ambiguous or skipped by a DST change
+fn local_timestamp(time: NaiveDateTime) -> u64 {
+    match Local.from_local_datetime(&time).earliest() {
+        Some(time) => time.timestamp().max(0) as u64,
+        None => Local.from_utc_datetime(&time).timestamp().max(0) as u64,
+    }
+}
+
+fn display_recovery_codes(codes: &[String]) {
+    println!("Here are your recovery codes, each one can be used once in place of a \
+    two-factor code. Keep them safe, they will not be shown again:");
+    for code in codes {
+        println!("{}", code);
+    }
 }
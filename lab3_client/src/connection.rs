@@ -2,6 +2,7 @@ use native_tls::TlsStream;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 use std::error::Error;
+use std::io::ErrorKind;
 use std::net::TcpStream;
 
 pub struct Connection {
@@ -26,4 +27,18 @@ impl Connection {
     {
         Ok(bincode::deserialize_from(&mut self.stream)?)
     }
+
+    /// Check without blocking if the server sent something that wasn't asked for, e.g. a shutdown notice
+    pub fn has_pending_data(&mut self) -> Result<bool, Box<dyn Error>> {
+        let socket = self.stream.get_ref();
+        socket.set_nonblocking(true)?;
+        // A closed connection is pending too, reading it gives the error
+        let pending = match socket.peek(&mut [0u8; 1]) {
+            Ok(_) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e.into()),
+        };
+        socket.set_nonblocking(false)?;
+        pending
+    }
 }
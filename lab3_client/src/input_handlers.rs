@@ -1,8 +1,13 @@
 use read_input::prelude::*;
+use chrono::{Days, Local, NaiveDate};
 use input_validation::phone_number::validate_phone_number;
-use input_validation::password::validate_password;
+use input_validation::password::{estimate_password_strength, validate_password,
+                                 MAX_PASSWORD_SCORE, RECOMMENDED_PASSWORD_SCORE};
 use input_validation::username::validate_username;
+use input_validation::two_factor_code::validate_two_factor_code;
+use input_validation::pin::validate_pin;
 use input_validation::messages::*;
+use input_validation::secret::Secret;
 
 pub fn ask_username() -> String {
     loop {
@@ -14,16 +19,41 @@ pub fn ask_username() -> String {
     }
 }
 
-pub fn ask_password() -> String {
+pub fn ask_password() -> Secret<String> {
     loop {
-        let password_input = input::<String>().msg("Please enter the password: ").get();
-        if validate_password(&password_input) {
+        let password_input = Secret::new(input::<String>().msg("Please enter the password: ").get());
+        if validate_password(password_input.expose()) {
             return password_input;
         }
         println!("{}", INVALID_PASSWORD.to_string());
     }
 }
 
+/// Ask a new password and show its strength with suggestions to improve it
+/// A weak password is only kept if the user confirms it
+pub fn ask_new_password() -> Secret<String> {
+    loop {
+        let password_input = Secret::new(input::<String>().msg("Please enter the new password: ").get());
+        if !validate_password(password_input.expose()) {
+            println!("{}", INVALID_PASSWORD);
+            continue;
+        }
+
+        let strength = estimate_password_strength(password_input.expose());
+        println!("Password strength: {}/{}", strength.score, MAX_PASSWORD_SCORE);
+        for suggestion in &strength.suggestions {
+            println!("  - {}", suggestion);
+        }
+        if strength.score >= RECOMMENDED_PASSWORD_SCORE
+            || input::<String>()
+                .msg("This password is weak, use it anyway? (y/n): ")
+                .get()
+                .eq_ignore_ascii_case("y") {
+            return password_input;
+        }
+    }
+}
+
 pub fn ask_phone_number() -> String {
     loop {
         let phone_input = input::<String>().msg("Please enter the phone number: ").get();
@@ -32,4 +62,67 @@ pub fn ask_phone_number() -> String {
         }
         println!("{}", INVALID_PHONE_NUMBER.to_string());
     }
+}
+
+pub fn ask_two_factor_code() -> String {
+    loop {
+        let code_input = input::<String>().msg("Please enter the two-factor or recovery code: ").get();
+        if validate_two_factor_code(&code_input) {
+            return code_input;
+        }
+        println!("{}", INVALID_TWO_FACTOR_CODE);
+    }
+}
+
+pub fn ask_pin() -> String {
+    loop {
+        let pin_input = input::<String>().msg("Please enter the PIN received by SMS: ").get();
+        if validate_pin(&pin_input) {
+            return pin_input;
+        }
+        println!("{}", INVALID_PIN);
+    }
+}
+
+pub fn ask_reconnect() -> bool {
+    input::<String>()
+        .msg("Connection lost, reconnect and resume the session? (y/n): ")
+        .get()
+        .eq_ignore_ascii_case("y")
+}
+
+/// Ask an username that can be left empty
+pub fn ask_optional_username(msg: &str) -> Option<String> {
+    loop {
+        let username_input = input::<String>().msg(format!("{} (empty for any): ", msg)).get();
+        if username_input.trim().is_empty() {
+            return None;
+        }
+        if validate_username(&username_input) {
+            return Some(username_input);
+        }
+        println!("{}", INVALID_USERNAME);
+    }
+}
+
+/// Ask a date as YYYY-MM-DD or as a number of days before today, it can be left empty
+pub fn ask_optional_date(msg: &str) -> Option<NaiveDate> {
+    loop {
+        let date_input = input::<String>()
+            .msg(format!("{} as YYYY-MM-DD or a number of days ago (empty for none): ", msg))
+            .get();
+        let date_input = date_input.trim();
+        if date_input.is_empty() {
+            return None;
+        }
+
+        let date = match date_input.parse::<u64>() {
+            Ok(days) => Local::now().date_naive().checked_sub_days(Days::new(days)),
+            Err(_) => NaiveDate::parse_from_str(date_input, "%Y-%m-%d").ok(),
+        };
+        match date {
+            Some(date) => return Some(date),
+            None => println!("Invalid date"),
+        }
+    }
 }
\ No newline at end of file
@@ -15,17 +15,44 @@ use std::net::TcpStream;
 use read_input::prelude::*;
 use crate::action::Action;
 use crate::connection::Connection;
+use crate::input_handlers::ask_reconnect;
+
+// Receive the banner, or the notice sent instead when the server is shutting down
+fn receive_banner(conn: &mut Connection) -> Result<Result<String, String>, Box<dyn Error>> {
+    let banner = conn.receive::<Result<String, String>>()?;
+    if let Err(notice) = &banner {
+        println!("{}", notice);
+    }
+    Ok(banner)
+}
 
 // Called once connected to the server, used to execute actions.
-fn client(conn: &mut Connection) -> Result<(), Box<dyn Error>> {
+// If a session token is given, the session is resumed first.
+// Returns once the server is shutting down.
+fn client(conn: &mut Connection, session_token: &mut Option<String>) -> Result<(), Box<dyn Error>> {
+    if session_token.is_some() {
+        if receive_banner(conn)?.is_err() {
+            return Ok(());
+        }
+        Action::ResumeSession.perform(conn, session_token)?;
+    }
+
     loop {
-        let banner = conn.receive::<String>()?;
-        println!("{}", banner);
+        match receive_banner(conn)? {
+            Ok(banner) => println!("{}", banner),
+            Err(_) => return Ok(()),
+        }
 
         Action::display();
         let action = input::<Action>().msg("Please select: ").get();
 
-        action.perform(conn)?;
+        // The server may have started shutting down while the user was choosing
+        if conn.has_pending_data()? {
+            let _notice = receive_banner(conn)?;
+            return Ok(());
+        }
+
+        action.perform(conn, session_token)?;
         println!();
     }
 }
@@ -39,6 +66,15 @@ fn load_server_cert(cert_file: &str) -> Certificate {
     Certificate::from_pem(&cert).unwrap()
 }
 
+// Open a new TLS connection to the server
+fn connect(connector: &TlsConnector) -> Result<Connection, String> {
+    let stream = TcpStream::connect(format!("{}:{}", SERVER_HOST, SERVER_PORT))
+        .map_err(|e| format!("Failed to connect to server: {}", e))?;
+    let stream = connector.connect(SERVER_HOST, stream)
+        .map_err(|e| format!("Failed to init TLS: {}", e))?;
+    Ok(Connection::new(stream))
+}
+
 const SERVER_HOST: &str = "localhost";
 const SERVER_PORT: &str = "4444";
 const SERVER_CERT_PATH: &str = "../lab3_server/keys/rsa_cert.pem";
@@ -54,24 +90,25 @@ fn main() {
         .build()
         .expect("Failed to build TlsConnector");
 
-    let stream = match TcpStream::connect(format!("{}:{}", SERVER_HOST, SERVER_PORT)) {
-        Ok(stream) => stream,
-        Err(e) => {
-            eprintln!("Failed to connect to server: {}", e);
-            return;
-        }
-    };
+    // The session token is kept to resume the session if the connection is lost
+    let mut session_token = None;
+    loop {
+        let mut conn = match connect(&connector) {
+            Ok(conn) => conn,
+            Err(e) => {
+                eprintln!("{}", e);
+                return;
+            }
+        };
 
-    let stream = match connector.connect(SERVER_HOST, stream) {
-        Ok(stream) => stream,
-        Err(e) => {
-            eprintln!("Failed to init TLS: {}", e);
-            return;
+        match client(&mut conn, &mut session_token) {
+            Ok(()) => return,
+            Err(e) => {
+                eprintln!("{}", e);
+                if session_token.is_none() || !ask_reconnect() {
+                    return;
+                }
+            }
         }
-    };
-
-    let mut conn = Connection::new(stream);
-    if let Err(e) = client(&mut conn) {
-        eprintln!("{}", e);
     }
 }
@@ -2,27 +2,55 @@ use casbin::CoreApi;
 use casbin::prelude::Enforcer;
 use std::error::Error;
 use log::warn;
+use strum::IntoEnumIterator;
 use crate::{Action, ConnectedUser, UserRole};
+use crate::user_store::UserStore;
+use crate::password_policy::{password_expiry, PasswordExpiry};
 
 // A Role-Based Access Control (RBAC) system will be implemented
 // Here a the roles and the actions that they can perform
-//                      |show users|change own phone|change phone|add user|login|logout|exit|
-// anonymous users:     |     x    |                |            |        |  x  |      |  x |
-// authenticated users: |     x    |        x       |            |        |     |   x  |  x |
-// HR users:            |     x    |        x       |      x     |    x   |     |   x  |  x |
+//                           |anonymous users|authenticated users|HR users|
+// show users:               |       x       |         x         |    x   |
+// change own phone:         |               |         x         |    x   |
+// confirm own phone:        |               |         x         |    x   |
+// change phone:             |               |                   |    x   |
+// add user:                 |               |                   |    x   |
+// enable 2fa:               |               |         x         |    x   |
+// regenerate recovery codes:|               |         x         |    x   |
+// reset 2fa:                |               |                   |    x   |
+// show own sessions:        |               |         x         |    x   |
+// kill own session:         |               |         x         |    x   |
+// show sessions:            |               |                   |    x   |
+// kill session:             |               |                   |    x   |
+// change own password:      |               |         x         |    x   |
+// reset password:           |               |                   |    x   |
+// search audit log:         |               |                   |    x   |
+// back up database:         |               |                   |    x   |
+// login:                    |       x       |                   |        |
+// logout:                   |               |         x         |    x   |
+// exit:                     |       x       |         x         |    x   |
+// resume session:           |       x       |                   |        |
 
 const ACCESS_CONTROL_CONF_FILE: &str = "./access_control.conf";
 const ACCESS_CONTROL_CSV_FILE: &str = "./access_control.csv";
 
 #[tokio::main]
-pub async fn can_perform_action(action: Action, user: &mut ConnectedUser) -> Result<bool, Box<dyn Error>> {
+pub async fn can_perform_action(action: Action, user: &mut ConnectedUser, store: &dyn UserStore) -> Result<bool, Box<dyn Error>> {
+    // An user with an expired password can only change it
+    if !user.is_anonymous()
+        && matches!(password_expiry(&user.user_account(store)?), PasswordExpiry::Expired)
+        && !matches!(action, Action::ChangeOwnPassword | Action::Logout | Action::Exit) {
+        warn!("User {} tried to perform {:?} with an expired password", user.username(), action);
+        return Ok(false);
+    }
+
     let mut e = Enforcer::new(
         ACCESS_CONTROL_CONF_FILE,
         ACCESS_CONTROL_CSV_FILE).await?;
     e.enable_log(true);
 
     match e.enforce((
-        get_user_role_string(user)?,
+        get_user_role_string(user, store)?,
         get_action_string(&action),
     )) {
         Ok(true) => Ok(true),
@@ -37,23 +65,48 @@ pub async fn can_perform_action(action: Action, user: &mut ConnectedUser) -> Res
     }
 }
 
-fn get_action_string(action: &Action) -> &'static str {
+/// Check that the access control policy can be loaded
+#[tokio::main]
+pub async fn check_access_control() -> Result<(), Box<dyn Error>> {
+    Enforcer::new(ACCESS_CONTROL_CONF_FILE, ACCESS_CONTROL_CSV_FILE).await?;
+    Ok(())
+}
+
+pub fn get_action_string(action: &Action) -> &'static str {
     match action {
         Action::ShowUsers => "show_users",
         Action::ChangeOwnPhone => "change_own_phone",
+        Action::ConfirmOwnPhone => "confirm_own_phone",
         Action::ChangePhone => "change_phone",
         Action::AddUser => "add_user",
+        Action::EnableTwoFactor => "enable_two_factor",
+        Action::RegenerateRecoveryCodes => "regenerate_recovery_codes",
+        Action::ResetTwoFactor => "reset_two_factor",
+        Action::ShowOwnSessions => "show_own_sessions",
+        Action::KillOwnSession => "kill_own_session",
+        Action::ShowSessions => "show_sessions",
+        Action::KillSession => "kill_session",
+        Action::ChangeOwnPassword => "change_own_password",
+        Action::ResetPassword => "reset_password",
+        Action::QueryAudit => "query_audit",
+        Action::CreateBackup => "create_backup",
         Action::Login => "login",
         Action::Logout => "logout",
         Action::Exit => "exit",
+        Action::ResumeSession => "resume_session",
     }
 }
 
-fn get_user_role_string(user: &mut ConnectedUser) -> Result<&str, Box<dyn Error>> {
+/// Check if a name is the name of an action, as recorded in the audit log
+pub fn is_action_string(name: &str) -> bool {
+    Action::iter().any(|action| get_action_string(&action) == name)
+}
+
+fn get_user_role_string(user: &mut ConnectedUser, store: &dyn UserStore) -> Result<&'static str, Box<dyn Error>> {
     if user.is_anonymous() {
         return Ok("anonymous");
     }
-    match user.user_account()?.role() {
+    match user.user_account(store)?.role() {
         UserRole::StandardUser => Ok("normal"),
         UserRole::HR => Ok("hr")
     }
@@ -4,20 +4,33 @@
 ///        - Input/output validation
 ///        - Log stuff whenever required
 ///        - Potential improvements
-use crate::database::Database;
 use crate::user::{UserAccount, UserAccountPublic, UserRole};
 use crate::user_connected::ConnectedUser;
+use crate::user_store::UserStore;
 use crate::messages::*;
 use crate::hashing_tools::*;
-use crate::access_control::can_perform_action;
+use crate::access_control::{can_perform_action, get_action_string, is_action_string};
+use crate::audit::{self, query_audit_log, AuditPage, AuditQuery};
+use crate::metrics;
+use crate::backup;
+use crate::two_factor::*;
+use crate::phone_verification::*;
+use crate::session_tokens::{revoke_session_token, revoke_user_session_tokens, validate_session_token};
+use crate::session_registry::{kill_session, kill_user_sessions, list_sessions, SessionInfo};
+use crate::password_policy::{check_new_password, set_password};
+use crate::user::TwoFactor;
 use serde::{Deserialize, Serialize};
 use std::error::Error;
 use strum_macros::{EnumIter, EnumString};
-use log::{info, trace, warn};
+use log::{error, info, trace, warn};
 use input_validation::phone_number::validate_phone_number;
 use input_validation::password::validate_password;
 use input_validation::username::validate_username;
+use input_validation::two_factor_code::validate_two_factor_code;
+use input_validation::pin::validate_pin;
 use input_validation::messages::*;
+use input_validation::log_escape::escape_log;
+use input_validation::secret::Secret;
 
 #[derive(Serialize, Deserialize, Debug, EnumString, EnumIter)]
 pub enum Action {
@@ -25,16 +38,42 @@ pub enum Action {
     ShowUsers,
     #[strum(serialize = "Change my phone number", serialize = "2")]
     ChangeOwnPhone,
-    #[strum(serialize = "Show someone's phone number", serialize = "3")]
+    #[strum(serialize = "Confirm my new phone number", serialize = "3")]
+    ConfirmOwnPhone,
+    #[strum(serialize = "Show someone's phone number", serialize = "4")]
     ChangePhone,
-    #[strum(serialize = "Add user", serialize = "4")]
+    #[strum(serialize = "Add user", serialize = "5")]
     AddUser,
-    #[strum(serialize = "Login", serialize = "5")]
+    #[strum(serialize = "Enable two-factor authentication", serialize = "6")]
+    EnableTwoFactor,
+    #[strum(serialize = "Regenerate my recovery codes", serialize = "7")]
+    RegenerateRecoveryCodes,
+    #[strum(serialize = "Reset someone's two-factor authentication", serialize = "8")]
+    ResetTwoFactor,
+    #[strum(serialize = "Show my sessions", serialize = "9")]
+    ShowOwnSessions,
+    #[strum(serialize = "Terminate one of my sessions", serialize = "10")]
+    KillOwnSession,
+    #[strum(serialize = "Show all sessions", serialize = "11")]
+    ShowSessions,
+    #[strum(serialize = "Terminate someone's session", serialize = "12")]
+    KillSession,
+    #[strum(serialize = "Change my password", serialize = "13")]
+    ChangeOwnPassword,
+    #[strum(serialize = "Reset someone's password", serialize = "14")]
+    ResetPassword,
+    #[strum(serialize = "Search the audit log", serialize = "15")]
+    QueryAudit,
+    #[strum(serialize = "Back up the database", serialize = "16")]
+    CreateBackup,
+    #[strum(serialize = "Login", serialize = "17")]
     Login,
-    #[strum(serialize = "Logout", serialize = "6")]
+    #[strum(serialize = "Logout", serialize = "18")]
     Logout,
-    #[strum(serialize = "Exit", serialize = "7")]
+    #[strum(serialize = "Exit", serialize = "19")]
     Exit,
+    #[strum(serialize = "Resume session")]
+    ResumeSession,
 }
 
 /// The individual actions are implemented with three main steps:
@@ -42,17 +81,32 @@ pub enum Action {
 ///     2. Execute various server code
 ///     3. Send a result
 impl Action {
-    pub fn perform(&self, u: &mut ConnectedUser) -> Result<(), Box<dyn Error>> {
+    pub fn perform(&self, u: &mut ConnectedUser, store: &dyn UserStore) -> Result<(), Box<dyn Error>> {
         info!("Performing action: {:?}", self);
 
         let res = match self {
-            Action::ShowUsers => Action::show_users(u),
-            Action::ChangeOwnPhone => Action::change_own_phone(u),
-            Action::ChangePhone => Action::change_phone(u),
-            Action::AddUser => Action::add_user(u),
-            Action::Login => Action::login(u),
-            Action::Logout => Action::logout(u),
+            Action::ShowUsers => Action::show_users(u, store),
+            Action::ChangeOwnPhone => Action::change_own_phone(u, store),
+            Action::ConfirmOwnPhone => Action::confirm_own_phone(u, store),
+            Action::ChangePhone => Action::change_phone(u, store),
+            Action::AddUser => Action::add_user(u, store),
+            Action::EnableTwoFactor => Action::enable_two_factor(u, store),
+            Action::RegenerateRecoveryCodes => Action::regenerate_recovery_codes(u, store),
+            Action::ResetTwoFactor => Action::reset_two_factor(u, store),
+            Action::ShowOwnSessions => Action::show_own_sessions(u, store),
+            Action::KillOwnSession => Action::kill_own_session(u, store),
+            Action::ShowSessions => Action::show_sessions(u, store),
+            Action::KillSession => Action::kill_session(u, store),
+            Action::ChangeOwnPassword => Action::change_own_password(u, store),
+            Action::ResetPassword => Action::reset_password(u, store),
+            Action::QueryAudit => Action::query_audit(u, store),
+            Action::CreateBackup => Action::create_backup(u, store),
+            Action::Login => Action::login(u, store),
+            Action::Logout => Action::logout(u, store),
+            Action::ResumeSession => Action::resume_session(u, store),
             Action::Exit => {
+                // The session can't be resumed after an explicit exit
+                u.logout();
                 info!("Client disconnected");
                 Err("Client disconnected")?
             },
@@ -61,13 +115,13 @@ impl Action {
         res
     }
 
-    pub fn show_users(u: &mut ConnectedUser) -> Result<(), Box<dyn Error>> {
+    pub fn show_users(u: &mut ConnectedUser, store: &dyn UserStore) -> Result<(), Box<dyn Error>> {
         trace!("Show users");
 
         // Check permissions
-        let res = if can_perform_action(Action::ShowUsers, u)? {
+        let res = if can_perform_action(Action::ShowUsers, u, store)? {
             // Update phone number
-            let users = Database::values()?;
+            let users = store.list()?;
             let mut users_public: Vec<UserAccountPublic> = vec![];
             for user in users {
                 users_public.push(UserAccountPublic {
@@ -82,10 +136,10 @@ impl Action {
         };
 
         //let res: Result<Vec<UserAccountPublic>, &str> = Ok(users_public);
-        u.conn().send(&res)
+        send_audited(u, Action::ShowUsers, None, &res)
     }
 
-    pub fn change_own_phone(u: &mut ConnectedUser) -> Result<(), Box<dyn Error>> {
+    pub fn change_own_phone(u: &mut ConnectedUser, store: &dyn UserStore) -> Result<(), Box<dyn Error>> {
         trace!("Change own phone number");
         let phone = u.conn().receive::<String>()?;
         let res;
@@ -93,60 +147,114 @@ impl Action {
         // Validate data
         if !validate_phone_number(&phone) {
             res = Err(INVALID_PHONE_NUMBER);
-            warn!("An user gave an invalid phone number: {}", phone);
-            return u.conn().send(&res);
+            warn!("An user gave an invalid phone number: {}", escape_log(&phone));
+            return send_audited_own(u, Action::ChangeOwnPhone, &res);
         }
 
         // Check permissions
-        res = if can_perform_action(Action::ChangeOwnPhone, u)? {
-            // Update phone number
-            let mut user = u.user_account()?;
-            user.set_phone_number(phone);
-            Database::insert(&user)?;
-            info!("User {} changed his phone number", user.username());
-            Ok(())
+        res = if can_perform_action(Action::ChangeOwnPhone, u, store)? {
+            // The phone number is updated once the PIN sent by SMS is confirmed
+            let mut user = u.user_account(store)?;
+            if phone_used_by_other(store, &phone, user.username())? {
+                warn!("User {} tried to change his phone number to the one of another account",
+                    user.username());
+                Err(PHONE_NUMBER_IN_USE)
+            } else {
+                start_phone_verification(&mut user, phone)?;
+                store.insert(&user)?;
+                info!("User {} asked to change his phone number, PIN sent by SMS", user.username());
+                Ok(())
+            }
         } else {
             warn!("Anonymous user tried to change own phone number without permission");
             Err(PERMISSION_DENIED)
         };
 
-        u.conn().send(&res)
+        send_audited_own(u, Action::ChangeOwnPhone, &res)
     }
 
-    pub fn change_phone(u: &mut ConnectedUser) -> Result<(), Box<dyn Error>> {
+    pub fn confirm_own_phone(u: &mut ConnectedUser, store: &dyn UserStore) -> Result<(), Box<dyn Error>> {
+        trace!("Confirm own phone number");
+        let pin = u.conn().receive::<String>()?;
+        let res;
+
+        // Validate data
+        if !validate_pin(&pin) {
+            res = Err(INVALID_PIN);
+            warn!("An user gave an invalid PIN format");
+            return send_audited_own(u, Action::ConfirmOwnPhone, &res);
+        }
+
+        // Check permissions
+        res = if can_perform_action(Action::ConfirmOwnPhone, u, store)? {
+            let mut user = u.user_account(store)?;
+            let confirmation = confirm_phone_number(&mut user, &pin);
+            store.insert(&user)?;
+            match confirmation {
+                PhoneConfirmation::Confirmed => {
+                    info!("User {} confirmed his new phone number", user.username());
+                    Ok(())
+                }
+                PhoneConfirmation::InvalidPin => {
+                    warn!("User {} gave a wrong PIN to confirm his phone number", user.username());
+                    Err(WRONG_PIN)
+                }
+                PhoneConfirmation::Expired => {
+                    warn!("User {} gave a PIN after its expiration", user.username());
+                    Err(PIN_EXPIRED)
+                }
+                PhoneConfirmation::NothingPending => {
+                    warn!("User {} tried to confirm a phone number but no change is pending",
+                        user.username());
+                    Err(NO_PENDING_PHONE)
+                }
+            }
+        } else {
+            warn!("Anonymous user tried to confirm own phone number without permission");
+            Err(PERMISSION_DENIED)
+        };
+
+        send_audited_own(u, Action::ConfirmOwnPhone, &res)
+    }
+
+    pub fn change_phone(u: &mut ConnectedUser, store: &dyn UserStore) -> Result<(), Box<dyn Error>> {
         trace!("Change phone number");
 
         // Receive data
         let username = u.conn().receive::<String>()?;
         let phone = u.conn().receive::<String>()?;
-        let target_user = Database::get(&username)?;
+        let target_user = store.get(&username)?;
         let res;
 
         // Validate data
         if !validate_username(&username) {
             res = Err(INVALID_USERNAME);
-            warn!("An user gave an invalid username: {}", username);
-            return u.conn().send(&res);
+            warn!("An user gave an invalid username: {}", escape_log(&username));
+            return send_audited(u, Action::ChangePhone, Some(&username), &res);
         }
         if !validate_phone_number(&phone) {
             res = Err(INVALID_PHONE_NUMBER);
-            warn!("An user gave an invalid phone number: {}", phone);
-            return u.conn().send(&res);
+            warn!("An user gave an invalid phone number: {}", escape_log(&phone));
+            return send_audited(u, Action::ChangePhone, Some(&username), &res);
         }
 
         // Check permissions
-        res = if can_perform_action(Action::ChangePhone, u)? {
+        res = if can_perform_action(Action::ChangePhone, u, store)? {
             if target_user.is_none() {
                 warn!("User {} tried to change phone number of user {} but he was not found",
                     u.username(), username);
                 Err(USER_NOT_FOUND)
+            } else if phone_used_by_other(store, &phone, &username)? {
+                warn!("User {} tried to change phone number of user {} to the one of another account",
+                    u.username(), username);
+                Err(PHONE_NUMBER_IN_USE)
             } else {
                 // Update phone number from target user
                 let mut target_user = target_user.unwrap();
                 info!("User {} changed phone number to {} for user: {}",
                     u.username(), phone, username);
                 target_user.set_phone_number(phone);
-                Database::insert(&target_user)?;
+                store.insert(&target_user)?;
                 Ok(())
             }
         } else {
@@ -154,15 +262,15 @@ impl Action {
             Err(PERMISSION_DENIED)
         };
 
-        u.conn().send(&res)
+        send_audited(u, Action::ChangePhone, Some(&username), &res)
     }
 
-    pub fn add_user(u: &mut ConnectedUser) -> Result<(), Box<dyn Error>> {
+    pub fn add_user(u: &mut ConnectedUser, store: &dyn UserStore) -> Result<(), Box<dyn Error>> {
         trace!("Adding user");
 
         // Receive data
         let username = u.conn().receive::<String>()?;
-        let password = u.conn().receive::<String>()?;
+        let password = u.conn().receive::<Secret<String>>()?;
         let phone = u.conn().receive::<String>()?;
         let role = u.conn().receive::<UserRole>()?;
         let res;
@@ -170,90 +278,479 @@ impl Action {
         // Validate data
         if !validate_username(&username) {
             res = Err(INVALID_USERNAME);
-            warn!("An user has given an invalid username: {}", username);
-            return u.conn().send(&res);
+            warn!("An user has given an invalid username: {}", escape_log(&username));
+            return send_audited(u, Action::AddUser, Some(&username), &res);
         }
-        if !validate_password(&password) {
+        if !validate_password(password.expose()) {
             res = Err(INVALID_PASSWORD);
-            warn!("An user has given an invalid password: {}", password);
-            return u.conn().send(&res);
+            warn!("An user has given an invalid password");
+            return send_audited(u, Action::AddUser, Some(&username), &res);
         }
         if !validate_phone_number(&phone) {
             res = Err(INVALID_PHONE_NUMBER);
-            warn!("An user has given an invalid phone number: {}", phone);
-            return u.conn().send(&res);
+            warn!("An user has given an invalid phone number: {}", escape_log(&phone));
+            return send_audited(u, Action::AddUser, Some(&username), &res);
         }
         // Role is validated and can't be false
         // because connection receive will return and throw an error before.
 
         // Hash password with a random salt
-        let (salt, hash_password) = new_hash_password(&password);
+        let hash_password = new_password_hash(&password);
 
         // Check permissions
-        res = if can_perform_action(Action::AddUser, u)? {
-            if Database::get(&username)?.is_some() {
+        res = if can_perform_action(Action::AddUser, u, store)? {
+            if store.get(&username)?.is_some() {
                 warn!("User {} tried to add an user that already exists: {}",
                     u.username(), username);
                 Err(USER_EXISTS)
+            } else if phone_used_by_other(store, &phone, &username)? {
+                warn!("User {} tried to add user {} with the phone number of another account",
+                    u.username(), username);
+                Err(PHONE_NUMBER_IN_USE)
+            } else if let Err(e) = check_new_password(&username, &password) {
+                warn!("User {} tried to add user {} with a refused password: {}",
+                    u.username(), username, e);
+                Err(e)
             } else {
-                info!("User {} added new user {}", u.username(), username);
-                let user = UserAccount::new(username, hash_password,
-                                            salt, phone, role);
-                Ok(Database::insert(&user)?)
+                let user = UserAccount::new(username.clone(), hash_password, phone, role);
+                // The username may have been taken since the check above
+                let mut added = false;
+                store.transaction(&mut |users| {
+                    added = users.get(&username)?.is_none();
+                    if added {
+                        users.insert(&user)?;
+                    }
+                    Ok(())
+                })?;
+
+                if added {
+                    info!("User {} added new user {}", u.username(), username);
+                    Ok(())
+                } else {
+                    warn!("User {} tried to add an user that already exists: {}",
+                        u.username(), username);
+                    Err(USER_EXISTS)
+                }
             }
         } else {
             warn!("A user tried to add user: {} without permission", username);
             Err(PERMISSION_DENIED)
         };
 
-        u.conn.send(&res)
+        send_audited(u, Action::AddUser, Some(&username), &res)
     }
 
-    pub fn login(u: &mut ConnectedUser) -> Result<(), Box<dyn Error>> {
-        trace!("Login");
+    pub fn enable_two_factor(u: &mut ConnectedUser, store: &dyn UserStore) -> Result<(), Box<dyn Error>> {
+        trace!("Enable two-factor authentication");
+
+        // Check permissions
+        if !can_perform_action(Action::EnableTwoFactor, u, store)? {
+            warn!("Anonymous user tried to enable two-factor authentication without permission");
+            let res: Result<(String, String), &str> = Err(PERMISSION_DENIED);
+            return send_audited_own(u, Action::EnableTwoFactor, &res);
+        }
+
+        let mut user = u.user_account(store)?;
+        if user.two_factor().is_some() {
+            warn!("User {} tried to enable two-factor authentication but it is already enabled",
+                user.username());
+            let res: Result<(String, String), &str> = Err(TWO_FACTOR_ALREADY_ENABLED);
+            return send_audited_own(u, Action::EnableTwoFactor, &res);
+        }
+
+        // Send the new secret and wait for a first code to confirm the enrollment
+        let secret = new_totp_secret();
+        let res: Result<(String, String), &str> =
+            Ok((secret.clone(), totp_url(&secret, user.username())?));
+        u.conn().send(&res)?;
+        let code = u.conn().receive::<String>()?;
+
+        let step = if validate_two_factor_code(&code) { check_totp_code(&secret, &code, 0) } else { None };
+        let res = if let Some(step) = step {
+            let (codes, hashes) = new_recovery_codes();
+            user.set_two_factor(Some(TwoFactor::new(secret, hashes, step)));
+            store.insert(&user)?;
+            info!("User {} enabled two-factor authentication", user.username());
+            Ok(codes)
+        } else {
+            warn!("User {} gave an invalid code to enable two-factor authentication",
+                user.username());
+            Err(TWO_FACTOR_INVALID_CODE)
+        };
+
+        send_audited_own(u, Action::EnableTwoFactor, &res)
+    }
+
+    pub fn regenerate_recovery_codes(u: &mut ConnectedUser, store: &dyn UserStore) -> Result<(), Box<dyn Error>> {
+        trace!("Regenerate recovery codes");
+
+        // Check permissions
+        let res = if can_perform_action(Action::RegenerateRecoveryCodes, u, store)? {
+            let mut user = u.user_account(store)?;
+            match user.two_factor_mut() {
+                Some(two_factor) => {
+                    let (codes, hashes) = new_recovery_codes();
+                    two_factor.set_recovery_codes(hashes);
+                    store.insert(&user)?;
+                    info!("User {} regenerated his recovery codes", user.username());
+                    Ok(codes)
+                }
+                None => {
+                    warn!("User {} tried to regenerate recovery codes without two-factor \
+                        authentication", user.username());
+                    Err(TWO_FACTOR_NOT_ENABLED)
+                }
+            }
+        } else {
+            warn!("Anonymous user tried to regenerate recovery codes without permission");
+            Err(PERMISSION_DENIED)
+        };
+
+        send_audited_own(u, Action::RegenerateRecoveryCodes, &res)
+    }
+
+    pub fn reset_two_factor(u: &mut ConnectedUser, store: &dyn UserStore) -> Result<(), Box<dyn Error>> {
+        trace!("Reset two-factor authentication");
 
         // Receive data
         let username = u.conn().receive::<String>()?;
-        let password = u.conn().receive::<String>()?;
         let res;
 
         // Validate data
         if !validate_username(&username) {
             res = Err(INVALID_USERNAME);
-            warn!("An user has given an invalid username: {}", username);
-            return u.conn().send(&res);
+            warn!("An user gave an invalid username: {}", escape_log(&username));
+            return send_audited(u, Action::ResetTwoFactor, Some(&username), &res);
         }
-        if !validate_password(&password) {
+
+        // Check permissions
+        res = if can_perform_action(Action::ResetTwoFactor, u, store)? {
+            match store.get(&username)? {
+                Some(mut target_user) => {
+                    // The identity of the target user is checked by HR before this action
+                    target_user.set_two_factor(None);
+                    store.insert(&target_user)?;
+                    info!("User {} reset two-factor authentication of user {}",
+                        u.username(), username);
+                    Ok(())
+                }
+                None => {
+                    warn!("User {} tried to reset two-factor authentication of user {} but he \
+                        was not found", u.username(), username);
+                    Err(USER_NOT_FOUND)
+                }
+            }
+        } else {
+            warn!("A user tried to reset two-factor authentication of user: {} without permission",
+                username);
+            Err(PERMISSION_DENIED)
+        };
+
+        send_audited(u, Action::ResetTwoFactor, Some(&username), &res)
+    }
+
+    pub fn show_own_sessions(u: &mut ConnectedUser, store: &dyn UserStore) -> Result<(), Box<dyn Error>> {
+        trace!("Show own sessions");
+
+        // Check permissions
+        let res: Result<Vec<SessionInfo>, &str> =
+            if can_perform_action(Action::ShowOwnSessions, u, store)? {
+                let username = u.username();
+                Ok(list_sessions(u.session_id(), Some(&username)))
+            } else {
+                warn!("Anonymous user tried to see own sessions without permission");
+                Err(PERMISSION_DENIED)
+            };
+
+        send_audited_own(u, Action::ShowOwnSessions, &res)
+    }
+
+    pub fn kill_own_session(u: &mut ConnectedUser, store: &dyn UserStore) -> Result<(), Box<dyn Error>> {
+        trace!("Kill own session");
+
+        // Receive data
+        let session_id = u.conn().receive::<u64>()?;
+
+        // Check permissions
+        let res = if can_perform_action(Action::KillOwnSession, u, store)? {
+            let username = u.username();
+            if session_id == u.session_id() {
+                warn!("User {} tried to terminate his current session", username);
+                Err(CANNOT_KILL_CURRENT_SESSION)
+            } else if kill_session(session_id, Some(&username)) {
+                info!("User {} terminated his session {}", username, session_id);
+                Ok(())
+            } else {
+                warn!("User {} tried to terminate session {} but it was not found",
+                    username, session_id);
+                Err(SESSION_NOT_FOUND)
+            }
+        } else {
+            warn!("Anonymous user tried to terminate a session without permission");
+            Err(PERMISSION_DENIED)
+        };
+
+        send_audited(u, Action::KillOwnSession, Some(&session_id.to_string()), &res)
+    }
+
+    pub fn show_sessions(u: &mut ConnectedUser, store: &dyn UserStore) -> Result<(), Box<dyn Error>> {
+        trace!("Show sessions");
+
+        // Check permissions
+        let res: Result<Vec<SessionInfo>, &str> = if can_perform_action(Action::ShowSessions, u, store)? {
+            Ok(list_sessions(u.session_id(), None))
+        } else {
+            warn!("A user tried to see all sessions without permission");
+            Err(PERMISSION_DENIED)
+        };
+
+        send_audited(u, Action::ShowSessions, None, &res)
+    }
+
+    pub fn kill_session(u: &mut ConnectedUser, store: &dyn UserStore) -> Result<(), Box<dyn Error>> {
+        trace!("Kill session");
+
+        // Receive data
+        let session_id = u.conn().receive::<u64>()?;
+
+        // Check permissions
+        let res = if can_perform_action(Action::KillSession, u, store)? {
+            if session_id == u.session_id() {
+                warn!("User {} tried to terminate his current session", u.username());
+                Err(CANNOT_KILL_CURRENT_SESSION)
+            } else if kill_session(session_id, None) {
+                info!("User {} terminated session {}", u.username(), session_id);
+                Ok(())
+            } else {
+                warn!("User {} tried to terminate session {} but it was not found",
+                    u.username(), session_id);
+                Err(SESSION_NOT_FOUND)
+            }
+        } else {
+            warn!("A user tried to terminate session {} without permission", session_id);
+            Err(PERMISSION_DENIED)
+        };
+
+        send_audited(u, Action::KillSession, Some(&session_id.to_string()), &res)
+    }
+
+    pub fn change_own_password(u: &mut ConnectedUser, store: &dyn UserStore) -> Result<(), Box<dyn Error>> {
+        trace!("Change own password");
+
+        // Receive data
+        let current_password = u.conn().receive::<Secret<String>>()?;
+        let password = u.conn().receive::<Secret<String>>()?;
+        let res;
+
+        // Validate data
+        if !validate_password(current_password.expose()) || !validate_password(password.expose()) {
             res = Err(INVALID_PASSWORD);
-            warn!("An user has given an invalid password: {}", password);
-            return u.conn().send(&res);
+            warn!("An user has given an invalid password");
+            return send_audited_own(u, Action::ChangeOwnPassword, &res);
         }
 
         // Check permissions
-        res = if can_perform_action(Action::Login, u)? {
-            let user = Database::get(&username)?;
-            let user_unwrapped;
-
-            // Default values
-            let mut user_salt: [u8; 16] = [0; 16]; // default
-            const DEFAULT: &str = "default";
-            let mut user_hash_password = DEFAULT;
-
-            if let Some(user) = user  {
-                user_unwrapped = user;
-                user_hash_password = user_unwrapped.hash_password();
-                user_salt = *user_unwrapped.salt();
+        res = if can_perform_action(Action::ChangeOwnPassword, u, store)? {
+            let mut user = u.user_account(store)?;
+            if !verify_password(&user.password_hash(), &current_password) {
+                warn!("User {} tried to change his password with a wrong current password",
+                    user.username());
+                Err(WRONG_CURRENT_PASSWORD)
             } else {
-                warn!("User tried to log but not found: {}", username);
+                match set_password(&mut user, &password) {
+                    Ok(()) => {
+                        info!("User {} changed his password", user.username());
+                        store.insert(&user)?;
+                        end_other_sessions(u, user.username());
+                        Ok(())
+                    }
+                    Err(e) => {
+                        warn!("User {} tried to set a refused password: {}", user.username(), e);
+                        Err(e)
+                    }
+                }
+            }
+        } else {
+            warn!("Anonymous user tried to change his password without permission");
+            Err(PERMISSION_DENIED)
+        };
+
+        send_audited_own(u, Action::ChangeOwnPassword, &res)
+    }
+
+    pub fn reset_password(u: &mut ConnectedUser, store: &dyn UserStore) -> Result<(), Box<dyn Error>> {
+        trace!("Reset password");
+
+        // Receive data
+        let username = u.conn().receive::<String>()?;
+        let password = u.conn().receive::<Secret<String>>()?;
+        let res;
+
+        // Validate data
+        if !validate_username(&username) {
+            res = Err(INVALID_USERNAME);
+            warn!("An user has given an invalid username: {}", escape_log(&username));
+            return send_audited(u, Action::ResetPassword, Some(&username), &res);
+        }
+        if !validate_password(password.expose()) {
+            res = Err(INVALID_PASSWORD);
+            warn!("An user has given an invalid password");
+            return send_audited(u, Action::ResetPassword, Some(&username), &res);
+        }
+
+        // Check permissions
+        res = if can_perform_action(Action::ResetPassword, u, store)? {
+            match store.get(&username)? {
+                Some(mut target_user) => match set_password(&mut target_user, &password) {
+                    Ok(()) => {
+                        info!("User {} reset the password of user {}", u.username(), username);
+                        store.insert(&target_user)?;
+                        end_other_sessions(u, &username);
+                        Ok(())
+                    }
+                    Err(e) => {
+                        warn!("User {} tried to set a refused password for user {}: {}",
+                            u.username(), username, e);
+                        Err(e)
+                    }
+                },
+                None => {
+                    warn!("User {} tried to reset the password of user {} but he was not found",
+                        u.username(), username);
+                    Err(USER_NOT_FOUND)
+                }
             }
+        } else {
+            warn!("A user tried to reset the password of user: {} without permission", username);
+            Err(PERMISSION_DENIED)
+        };
+
+        send_audited(u, Action::ResetPassword, Some(&username), &res)
+    }
+
+    pub fn query_audit(u: &mut ConnectedUser, store: &dyn UserStore) -> Result<(), Box<dyn Error>> {
+        trace!("Query audit");
+
+        // Receive data
+        let query = u.conn().receive::<AuditQuery>()?;
+        let res: Result<AuditPage, &str>;
+        let target = query.target.clone();
+
+        // Validate data
+        if !query.actor.as_deref().is_none_or(validate_username)
+            || !query.target.as_deref().is_none_or(validate_username) {
+            res = Err(INVALID_USERNAME);
+            warn!("An user has given an invalid username in an audit query");
+            return send_audited(u, Action::QueryAudit, target.as_deref(), &res);
+        }
+        if !query.action.as_deref().is_none_or(is_action_string) {
+            res = Err(UNKNOWN_ACTION);
+            warn!("An user has given an unknown action in an audit query");
+            return send_audited(u, Action::QueryAudit, target.as_deref(), &res);
+        }
+        if query.page == 0 || matches!((query.from, query.to), (Some(from), Some(to)) if from > to) {
+            res = Err(INVALID_AUDIT_QUERY);
+            warn!("An user has given an invalid time range or page in an audit query");
+            return send_audited(u, Action::QueryAudit, target.as_deref(), &res);
+        }
+
+        // Check permissions
+        res = if can_perform_action(Action::QueryAudit, u, store)? {
+            match query_audit_log(&query) {
+                Ok(page) => {
+                    info!("User {} searched the audit log: {:?}", u.username(), query);
+                    Ok(page)
+                }
+                Err(e) => {
+                    error!("An error occurred reading the audit log: {}", e);
+                    Err(AUDIT_LOG_UNAVAILABLE)
+                }
+            }
+        } else {
+            warn!("A user tried to search the audit log without permission");
+            Err(PERMISSION_DENIED)
+        };
+
+        send_audited(u, Action::QueryAudit, target.as_deref(), &res)
+    }
+
+    pub fn create_backup(u: &mut ConnectedUser, store: &dyn UserStore) -> Result<(), Box<dyn Error>> {
+        trace!("Create backup");
+
+        // Check permissions
+        let res = if can_perform_action(Action::CreateBackup, u, store)? {
+            match backup::create_backup(store) {
+                Ok(name) => {
+                    info!("User {} made the backup {}", u.username(), name);
+                    Ok(name)
+                }
+                Err(e) => {
+                    error!("An error occurred making a backup: {}", e);
+                    Err(BACKUP_FAILED)
+                }
+            }
+        } else {
+            warn!("A user tried to back up the database without permission");
+            Err(PERMISSION_DENIED)
+        };
+
+        send_audited(u, Action::CreateBackup, None, &res)
+    }
+
+    pub fn login(u: &mut ConnectedUser, store: &dyn UserStore) -> Result<(), Box<dyn Error>> {
+        trace!("Login");
+
+        // Receive data
+        let username = u.conn().receive::<String>()?;
+        let password = u.conn().receive::<Secret<String>>()?;
+        let res: Result<Option<String>, &str>;
+
+        // Validate data
+        if !validate_username(&username) {
+            res = Err(INVALID_USERNAME);
+            warn!("An user has given an invalid username: {}", escape_log(&username));
+            return send_audited(u, Action::Login, Some(&username), &res);
+        }
+        if !validate_password(password.expose()) {
+            res = Err(INVALID_PASSWORD);
+            warn!("An user has given an invalid password");
+            return send_audited(u, Action::Login, Some(&username), &res);
+        }
+
+        // Check permissions
+        // A session token is given once logged in, nothing if a second factor is required
+        let mut second_factor_user = None;
+        res = if can_perform_action(Action::Login, u, store)? {
+            let mut user = store.get(&username)?;
+
             // Compare hash of passwords and do it always
-            if user_hash_password == &hash_argon2(&password, &user_salt)
-                && user_hash_password != DEFAULT {
-                u.set_username(&username);
-                info!("User {} logged in", username);
-                Ok(())
+            let password_valid = match &user {
+                Some(user) => verify_password(&user.password_hash(), &password),
+                None => {
+                    warn!("User tried to log but not found: {}", username);
+                    verify_dummy_hash(&password);
+                    false
+                }
+            };
+            if password_valid {
+                // Upgrade the hash made with older parameters or pepper
+                if let Some(user) = user.as_mut().filter(|user| password_needs_rehash(&user.password_hash())) {
+                    user.set_password_hash(new_password_hash(&password));
+                    store.insert(user)?;
+                    info!("Password hash of user {} upgraded to the current parameters", username);
+                }
+
+                let two_factor_required = user.as_ref()
+                    .is_some_and(|user| user.two_factor().is_some());
+                if two_factor_required {
+                    second_factor_user = user;
+                    Ok(None)
+                } else {
+                    info!("User {} logged in", username);
+                    Ok(Some(u.login(&username)))
+                }
             } else {
-                warn!("User {} logged with an invalid password {}", username, password);
+                warn!("User {} logged with an invalid password", username);
                 Err(LOGIN_FAIL)
             }
         } else {
@@ -261,22 +758,379 @@ impl Action {
             Err(PERMISSION_DENIED)
         };
 
-        u.conn.send(&res)
+        // The login is recorded once the second factor has been checked
+        if second_factor_user.is_some() {
+            u.conn().send(&res)?;
+        } else {
+            send_audited(u, Action::Login, Some(&username), &res)?;
+        }
+
+        match second_factor_user {
+            Some(user) => Action::login_second_factor(u, store, user),
+            None => Ok(()),
+        }
+    }
+
+    fn login_second_factor(u: &mut ConnectedUser, store: &dyn UserStore, user: UserAccount) -> Result<(), Box<dyn Error>> {
+        trace!("Login second factor");
+
+        // Receive data
+        let code = u.conn().receive::<String>()?;
+        let res: Result<String, &str>;
+
+        // Validate data
+        if !validate_two_factor_code(&code) {
+            res = Err(INVALID_TWO_FACTOR_CODE);
+            warn!("User {} has given an invalid second factor code format", user.username());
+            return send_audited(u, Action::Login, Some(user.username()), &res);
+        }
+
+        // The code is checked against the stored account and used up in the same transaction,
+        // so a code can't be used twice, even by two logins at the same time
+        let mut check = SecondFactorCheck::Invalid;
+        let mut remaining = 0;
+        store.transaction(&mut |users| {
+            check = SecondFactorCheck::Invalid;
+            let mut current = match users.get(user.username())? {
+                Some(current) => current,
+                None => return Ok(()),
+            };
+            if let Some(two_factor) = current.two_factor_mut() {
+                check = verify_second_factor(two_factor, &code);
+                remaining = two_factor.recovery_codes().len();
+            }
+            if !matches!(check, SecondFactorCheck::Invalid) {
+                users.insert(&current)?;
+            }
+            Ok(())
+        })?;
+        res = match check {
+            SecondFactorCheck::Totp => {
+                info!("User {} logged in", user.username());
+                Ok(u.login(user.username()))
+            }
+            SecondFactorCheck::RecoveryCode => {
+                info!("User {} logged in with a recovery code, {} remaining", user.username(), remaining);
+                Ok(u.login(user.username()))
+            }
+            SecondFactorCheck::Invalid => {
+                warn!("User {} logged with an invalid second factor code", user.username());
+                Err(LOGIN_FAIL)
+            }
+        };
+
+        send_audited(u, Action::Login, Some(user.username()), &res)
+    }
+
+    pub fn resume_session(u: &mut ConnectedUser, store: &dyn UserStore) -> Result<(), Box<dyn Error>> {
+        trace!("Resume session");
+
+        // Receive data
+        let token = u.conn().receive::<String>()?;
+
+        // Check permissions
+        let res = if can_perform_action(Action::ResumeSession, u, store)? {
+            match validate_session_token(&token) {
+                Some(username) if store.get(&username)?.is_some() => {
+                    // A new token is given so the old one can't be used again
+                    revoke_session_token(&token);
+                    info!("User {} resumed his session", username);
+                    Ok(u.login(&username))
+                }
+                _ => {
+                    warn!("An user tried to resume a session with an invalid or expired token");
+                    Err(INVALID_SESSION_TOKEN)
+                }
+            }
+        } else {
+            warn!("User {} tried to resume a session without permission", u.username());
+            Err(PERMISSION_DENIED)
+        };
+
+        send_audited_own(u, Action::ResumeSession, &res)
     }
 
-    pub fn logout(u: &mut ConnectedUser) -> Result<(), Box<dyn Error>> {
+    pub fn logout(u: &mut ConnectedUser, store: &dyn UserStore) -> Result<(), Box<dyn Error>> {
         trace!("Logout");
 
-        let res = if can_perform_action(Action::Logout, u)? {
-            // Logout
+        let res = if can_perform_action(Action::Logout, u, store)? {
             info!("User {} logged out", u.username());
-            u.logout();
             Ok(())
         } else {
             warn!("Anonymous tried to logout without permission");
             Err(PERMISSION_DENIED)
         };
 
+        // Recorded before the logout to keep the user as actor
+        let outcome = audit::record(u, get_action_string(&Action::Logout), None, res.err());
+        metrics::record_action(get_action_string(&Action::Logout), outcome);
+        if res.is_ok() {
+            u.logout();
+        }
         u.conn.send(&res)
     }
-}
\ No newline at end of file
+}
+
+/// Check if a phone number is used by another account than the given one
+/// # Arguments
+/// * `store` - store of the user accounts
+/// * `phone` - phone number to look up
+/// * `username` - account allowed to use the phone number
+fn phone_used_by_other(store: &dyn UserStore, phone: &str, username: &str) -> Result<bool, Box<dyn Error>> {
+    Ok(store.get_by_phone(phone)?.iter().any(|user| user.username() != username))
+}
+
+/// End the sessions of an user whose password has changed, they may have been opened by someone knowing
+/// the former password. The current session of the user performing the action is kept
+/// # Arguments
+/// * `u` - connected user who changed the password
+/// * `username` - user whose password has changed
+fn end_other_sessions(u: &ConnectedUser, username: &str) {
+    revoke_user_session_tokens(username, u.session_token());
+    let count = kill_user_sessions(username, Some(u.session_id()));
+    if count > 0 {
+        info!("{} sessions of user {} terminated after the change of his password", count, username);
+    }
+}
+
+/// Send the result of an action to the client, record it in the audit log and count it in the metrics
+/// # Arguments
+/// * `u` - connected user performing the action
+/// * `action` - action performed
+/// * `target` - user or session targeted by the action, if any
+/// * `res` - result sent to the client
+fn send_audited<T: Serialize>(u: &mut ConnectedUser, action: Action, target: Option<&str>,
+                              res: &Result<T, &str>) -> Result<(), Box<dyn Error>> {
+    let outcome = audit::record(u, get_action_string(&action), target, res.as_ref().err().copied());
+    metrics::record_action(get_action_string(&action), outcome);
+    u.conn().send(res)
+}
+
+/// Send the result of an action on the account of the connected user, who is recorded as its target
+/// # Arguments
+/// * `u` - connected user performing the action
+/// * `action` - action performed
+/// * `res` - result sent to the client
+fn send_audited_own<T: Serialize>(u: &mut ConnectedUser, action: Action,
+                                  res: &Result<T, &str>) -> Result<(), Box<dyn Error>> {
+    let target = u.logged_username().map(str::to_string);
+    send_audited(u, action, target.as_deref(), res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::user_store::MemoryUserStore;
+    use crate::Connection;
+    use std::net::{TcpListener, TcpStream};
+
+    const PASSWORD: &str = "Passw0rd!test";
+
+    // The actions are called on a connection whose client is played by the test,
+    // the data they receive is sent before they are called
+    fn connect(username: Option<&str>) -> (ConnectedUser, Connection) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        let mut u = ConnectedUser::anonymous(Connection::plain(server)).unwrap();
+        if let Some(username) = username {
+            u.login(username);
+        }
+        (u, Connection::plain(client))
+    }
+
+    fn account(username: &str, role: UserRole) -> UserAccount {
+        UserAccount::new(username.to_string(), new_password_hash(&Secret::new(PASSWORD.to_string())),
+                         "0791112233".to_string(), role)
+    }
+
+    fn account_with_two_factor(username: &str, secret: &str) -> (UserAccount, Vec<String>) {
+        let mut user = account(username, UserRole::StandardUser);
+        let (codes, hashes) = new_recovery_codes();
+        user.set_two_factor(Some(TwoFactor::new(secret.to_string(), hashes, 0)));
+        (user, codes)
+    }
+
+    // Login with a second factor code, returns the session token if the login succeeded
+    fn login_two_factor(store: &dyn UserStore, username: &str, code: &str) -> Result<String, String> {
+        let (mut u, mut client) = connect(None);
+        client.send(&username.to_string()).unwrap();
+        client.send(&Secret::new(PASSWORD.to_string())).unwrap();
+        client.send(&code.to_string()).unwrap();
+        Action::login(&mut u, store).unwrap();
+        assert_eq!(client.receive::<Result<Option<String>, String>>().unwrap(), Ok(None));
+        client.receive::<Result<String, String>>().unwrap()
+    }
+
+    #[test]
+    fn test_login_recovery_code() {
+        let (user, codes) = account_with_two_factor("login-rc", &new_totp_secret());
+        let store = MemoryUserStore::new(vec![user]);
+
+        assert!(login_two_factor(&store, "login-rc", &codes[0]).is_ok());
+        assert_eq!(login_two_factor(&store, "login-rc", &codes[0]), Err(LOGIN_FAIL.to_string()));
+        let two_factor = store.get("login-rc").unwrap().unwrap().two_factor().cloned().unwrap();
+        assert_eq!(two_factor.recovery_codes().len(), codes.len() - 1);
+    }
+
+    #[test]
+    fn test_login_totp_replay() {
+        let secret = new_totp_secret();
+        let (user, _) = account_with_two_factor("login-totp", &secret);
+        let store = MemoryUserStore::new(vec![user]);
+        let code = current_totp_code(&secret);
+
+        assert!(login_two_factor(&store, "login-totp", &code).is_ok());
+        assert_eq!(login_two_factor(&store, "login-totp", &code), Err(LOGIN_FAIL.to_string()));
+    }
+
+    #[test]
+    fn test_regenerate_recovery_codes() {
+        let (user, old_codes) = account_with_two_factor("regen-alice", &new_totp_secret());
+        let store = MemoryUserStore::new(vec![user, account("regen-bob", UserRole::StandardUser)]);
+
+        let (mut u, mut client) = connect(Some("regen-alice"));
+        Action::regenerate_recovery_codes(&mut u, &store).unwrap();
+        let codes = client.receive::<Result<Vec<String>, String>>().unwrap().unwrap();
+        let mut two_factor = store.get("regen-alice").unwrap().unwrap().two_factor().cloned().unwrap();
+        assert!(matches!(verify_second_factor(&mut two_factor, &old_codes[0]), SecondFactorCheck::Invalid));
+        assert!(matches!(verify_second_factor(&mut two_factor, &codes[0]), SecondFactorCheck::RecoveryCode));
+
+        // Without two-factor authentication
+        let (mut u, mut client) = connect(Some("regen-bob"));
+        Action::regenerate_recovery_codes(&mut u, &store).unwrap();
+        assert_eq!(client.receive::<Result<Vec<String>, String>>().unwrap(),
+                   Err(TWO_FACTOR_NOT_ENABLED.to_string()));
+    }
+
+    #[test]
+    fn test_reset_two_factor() {
+        let (user, _) = account_with_two_factor("reset-2fa-bob", &new_totp_secret());
+        let store = MemoryUserStore::new(vec![
+            user,
+            account("reset-2fa-hr", UserRole::HR),
+            account("reset-2fa-carol", UserRole::StandardUser),
+        ]);
+
+        // Only HR can clear the second factor
+        let (mut u, mut client) = connect(Some("reset-2fa-carol"));
+        client.send(&"reset-2fa-bob".to_string()).unwrap();
+        Action::reset_two_factor(&mut u, &store).unwrap();
+        assert_eq!(client.receive::<Result<(), String>>().unwrap(), Err(PERMISSION_DENIED.to_string()));
+        assert!(store.get("reset-2fa-bob").unwrap().unwrap().two_factor().is_some());
+
+        let (mut u, mut client) = connect(Some("reset-2fa-hr"));
+        client.send(&"reset-2fa-bob".to_string()).unwrap();
+        Action::reset_two_factor(&mut u, &store).unwrap();
+        assert_eq!(client.receive::<Result<(), String>>().unwrap(), Ok(()));
+        assert!(store.get("reset-2fa-bob").unwrap().unwrap().two_factor().is_none());
+
+        let (mut u, mut client) = connect(Some("reset-2fa-hr"));
+        client.send(&"reset-2fa-dave".to_string()).unwrap();
+        Action::reset_two_factor(&mut u, &store).unwrap();
+        assert_eq!(client.receive::<Result<(), String>>().unwrap(), Err(USER_NOT_FOUND.to_string()));
+    }
+
+    #[test]
+    fn test_change_phone() {
+        let mut bob = account("phone-bob", UserRole::StandardUser);
+        bob.set_phone_number("0794445566".to_string());
+        let store = MemoryUserStore::new(vec![
+            account("phone-hr", UserRole::HR),
+            bob,
+            account("phone-carol", UserRole::StandardUser),
+        ]);
+        let change_phone = |actor: &str, username: &str, phone: &str| {
+            let (mut u, mut client) = connect(Some(actor));
+            client.send(&username.to_string()).unwrap();
+            client.send(&phone.to_string()).unwrap();
+            Action::change_phone(&mut u, &store).unwrap();
+            client.receive::<Result<(), String>>().unwrap()
+        };
+
+        assert_eq!(change_phone("phone-carol", "phone-bob", "0797778899"), Err(PERMISSION_DENIED.to_string()));
+        assert_eq!(change_phone("phone-hr", "phone-dave", "0797778899"), Err(USER_NOT_FOUND.to_string()));
+        assert_eq!(change_phone("phone-hr", "phone-bob", "0791112233"), Err(PHONE_NUMBER_IN_USE.to_string()));
+        assert_eq!(change_phone("phone-hr", "phone-bob", "not a phone"), Err(INVALID_PHONE_NUMBER.to_string()));
+        assert_eq!(store.get("phone-bob").unwrap().unwrap().phone_number(), "0794445566");
+
+        assert_eq!(change_phone("phone-hr", "phone-bob", "0797778899"), Ok(()));
+        assert_eq!(store.get("phone-bob").unwrap().unwrap().phone_number(), "0797778899");
+    }
+
+    #[test]
+    fn test_add_user() {
+        let store = MemoryUserStore::new(vec![account("add-hr", UserRole::HR)]);
+        let add_user = |username: &str, phone: &str| {
+            let (mut u, mut client) = connect(Some("add-hr"));
+            client.send(&username.to_string()).unwrap();
+            client.send(&Secret::new("Xk9#mQ2!vLp7zR".to_string())).unwrap();
+            client.send(&phone.to_string()).unwrap();
+            client.send(&UserRole::StandardUser).unwrap();
+            Action::add_user(&mut u, &store).unwrap();
+            client.receive::<Result<(), String>>().unwrap()
+        };
+
+        assert_eq!(add_user("add-bob", "0794445566"), Ok(()));
+        assert_eq!(store.get("add-bob").unwrap().unwrap().phone_number(), "0794445566");
+        assert_eq!(add_user("add-bob", "0797778899"), Err(USER_EXISTS.to_string()));
+        assert_eq!(add_user("add-carol", "0794445566"), Err(PHONE_NUMBER_IN_USE.to_string()));
+        assert_eq!(store.get("add-bob").unwrap().unwrap().phone_number(), "0794445566");
+        assert!(store.get("add-carol").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_reset_password() {
+        let store = MemoryUserStore::new(vec![
+            account("reset-pw-hr", UserRole::HR),
+            account("reset-pw-bob", UserRole::StandardUser),
+        ]);
+        let (bob, _bob_client) = connect(Some("reset-pw-bob"));
+        let bob_token = bob.session_token().unwrap().to_string();
+
+        let (mut u, mut client) = connect(Some("reset-pw-hr"));
+        client.send(&"reset-pw-bob".to_string()).unwrap();
+        client.send(&Secret::new("Xk9#mQ2!vLp7zR".to_string())).unwrap();
+        Action::reset_password(&mut u, &store).unwrap();
+        assert_eq!(client.receive::<Result<(), String>>().unwrap(), Ok(()));
+
+        let user = store.get("reset-pw-bob").unwrap().unwrap();
+        assert!(verify_password(&user.password_hash(), &Secret::new("Xk9#mQ2!vLp7zR".to_string())));
+        // The sessions opened with the former password are ended
+        assert!(validate_session_token(&bob_token).is_none());
+        assert!(list_sessions(u.session_id(), Some("reset-pw-bob")).is_empty());
+        assert!(validate_session_token(u.session_token().unwrap()).is_some());
+    }
+
+    #[test]
+    fn test_change_own_password() {
+        let store = MemoryUserStore::new(vec![account("own-pw-alice", UserRole::StandardUser)]);
+        let (other, _other_client) = connect(Some("own-pw-alice"));
+        let other_token = other.session_token().unwrap().to_string();
+
+        let (mut u, mut client) = connect(Some("own-pw-alice"));
+        client.send(&Secret::new(PASSWORD.to_string())).unwrap();
+        client.send(&Secret::new("Xk9#mQ2!vLp7zR".to_string())).unwrap();
+        Action::change_own_password(&mut u, &store).unwrap();
+        assert_eq!(client.receive::<Result<(), String>>().unwrap(), Ok(()));
+
+        // Only the current session is kept
+        assert!(validate_session_token(&other_token).is_none());
+        assert_eq!(validate_session_token(u.session_token().unwrap()).as_deref(), Some("own-pw-alice"));
+        let sessions = list_sessions(u.session_id(), Some("own-pw-alice"));
+        assert_eq!(sessions.len(), 1);
+        assert!(sessions[0].current);
+
+        // The user is recorded as the target of the change of his own password, the most recent event comes first
+        let page = query_audit_log(&AuditQuery {
+            actor: Some("own-pw-alice".to_string()),
+            target: None,
+            action: Some(get_action_string(&Action::ChangeOwnPassword).to_string()),
+            outcome: None,
+            from: None,
+            to: None,
+            page: 1,
+        }).unwrap();
+        assert_eq!(page.events[0].target.as_deref(), Some("own-pw-alice"));
+    }
+}
@@ -0,0 +1,419 @@
+/// This file is used to record the security events in a dedicated audit log
+/// The audit log is an append-only JSON-lines file, each record contains the hash of the previous one
+/// so that a deleted or modified record breaks the chain
+use crate::audit_sink::{open_sinks, AuditSink};
+use crate::env_reader::CONFIG;
+use crate::messages::PERMISSION_DENIED;
+use crate::time_tools::now_timestamp;
+use crate::user_connected::ConnectedUser;
+use lazy_static::lazy_static;
+use log::{error, trace, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::str;
+use std::sync::Mutex;
+
+// Previous hash of the first record
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+// Number of events sent per page of an audit log query
+const AUDIT_PAGE_SIZE: usize = 20;
+// Extension of the file where a last record cut by a crash is moved
+const INCOMPLETE_EXTENSION: &str = "incomplete";
+const POISONED_AUDIT_LOG: &str = "The audit log is unusable after a panic";
+
+lazy_static! {
+    // The server can't work without its audit log
+    static ref AUDIT_LOG: Mutex<AuditLog> = match AuditLog::open(&CONFIG.audit_log_path) {
+        Ok(log) => Mutex::new(log),
+        Err(e) => {
+            error!("An error occurred opening the audit log: {}", e);
+            panic!("An error occurred opening the audit log: {}", e)
+        }
+    };
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditOutcome {
+    Success,
+    Failure,
+    Denied,
+}
+
+/// A record of the audit log
+/// The hash is computed on the JSON of the record without the hash
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AuditRecord {
+    pub seq: u64,
+    pub timestamp: u64,
+    pub actor: Option<String>,
+    pub action: String,
+    pub target: Option<String>,
+    pub outcome: AuditOutcome,
+    pub reason: Option<String>,
+    pub peer_ip: String,
+    pub prev_hash: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub hash: String,
+}
+
+/// Filters of an audit log query, a filter set to None matches every record
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AuditQuery {
+    pub actor: Option<String>,
+    pub target: Option<String>,
+    pub action: Option<String>,
+    pub outcome: Option<AuditOutcome>,
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+    // Starts at 1, the most recent events come first
+    pub page: u32,
+}
+
+/// An audit record sent to the client, without its hashes
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AuditEvent {
+    pub seq: u64,
+    pub timestamp: u64,
+    pub actor: Option<String>,
+    pub action: String,
+    pub target: Option<String>,
+    pub outcome: AuditOutcome,
+    pub reason: Option<String>,
+    pub peer_ip: String,
+}
+
+/// A page of the events matching an audit log query
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AuditPage {
+    pub events: Vec<AuditEvent>,
+    pub page: u32,
+    pub page_count: u32,
+    pub total: u64,
+}
+
+impl AuditQuery {
+    fn matches(&self, record: &AuditRecord) -> bool {
+        (self.actor.is_none() || record.actor == self.actor)
+            && (self.target.is_none() || record.target == self.target)
+            && self.action.as_ref().is_none_or(|action| *action == record.action)
+            && self.outcome.is_none_or(|outcome| outcome == record.outcome)
+            && self.from.is_none_or(|from| record.timestamp >= from)
+            && self.to.is_none_or(|to| record.timestamp <= to)
+    }
+}
+
+impl From<AuditRecord> for AuditEvent {
+    fn from(record: AuditRecord) -> Self {
+        AuditEvent {
+            seq: record.seq,
+            timestamp: record.timestamp,
+            actor: record.actor,
+            action: record.action,
+            target: record.target,
+            outcome: record.outcome,
+            reason: record.reason,
+            peer_ip: record.peer_ip,
+        }
+    }
+}
+
+impl AuditRecord {
+    fn compute_hash(&self) -> Result<String, Box<dyn Error>> {
+        let unhashed = AuditRecord { hash: String::new(), ..self.clone() };
+        Ok(format!("{:x}", Sha256::digest(serde_json::to_string(&unhashed)?.as_bytes())))
+    }
+}
+
+struct AuditLog {
+    file: File,
+    next_seq: u64,
+    last_hash: String,
+    sinks: Vec<Box<dyn AuditSink>>,
+}
+
+impl AuditLog {
+    /// Open the audit log and continue the chain from its last record, with the sinks of the config
+    /// A last record cut by a crash is moved to `<path>.incomplete`, the chain continues from the record before it
+    fn open(path: &str) -> Result<AuditLog, Box<dyn Error>> {
+        let mut content = match fs::read(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        // The records are written with their new line, a last line without one was cut
+        let complete = content.iter().rposition(|b| *b == b'\n').map_or(0, |i| i + 1);
+        if complete < content.len() {
+            if serde_json::from_slice::<AuditRecord>(&content[complete..]).is_ok() {
+                writeln!(file)?;
+            } else {
+                let incomplete = format!("{}.{}", path, INCOMPLETE_EXTENSION);
+                let mut incomplete_file = OpenOptions::new().create(true).append(true).open(&incomplete)?;
+                incomplete_file.write_all(&content[complete..])?;
+                writeln!(incomplete_file)?;
+                incomplete_file.sync_all()?;
+                file.set_len(complete as u64)?;
+                content.truncate(complete);
+                warn!("The last record of the audit log {} was cut, it has been moved to {}", path, incomplete);
+            }
+            file.sync_all()?;
+        }
+
+        let (next_seq, last_hash) = match str::from_utf8(&content)?.lines().rfind(|l| !l.trim().is_empty()) {
+            Some(line) => {
+                let last: AuditRecord = serde_json::from_str(line)?;
+                (last.seq + 1, last.hash)
+            }
+            None => (0, GENESIS_HASH.to_string()),
+        };
+        let sinks = open_sinks(&CONFIG.audit_sinks, &CONFIG.audit_cef_path, &CONFIG.audit_syslog_address)?;
+        Ok(AuditLog { file, next_seq, last_hash, sinks })
+    }
+
+    fn append(&mut self, mut record: AuditRecord) -> Result<(), Box<dyn Error>> {
+        record.seq = self.next_seq;
+        record.prev_hash = self.last_hash.clone();
+        record.hash = record.compute_hash()?;
+        writeln!(self.file, "{}", serde_json::to_string(&record)?)?;
+
+        self.next_seq += 1;
+        self.last_hash = record.hash.clone();
+
+        // The record is kept in the audit log even if it can't be exported
+        for sink in &mut self.sinks {
+            if let Err(e) = sink.send(&record) {
+                error!("An error occurred exporting an audit record: {}", e);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Open the audit log at startup
+pub fn init() {
+    let _ = &*AUDIT_LOG;
+}
+
+/// Write the audit log to the disk, e.g. before stopping the server
+pub fn flush() -> Result<(), Box<dyn Error>> {
+    let log = AUDIT_LOG.lock().map_err(|_| POISONED_AUDIT_LOG)?;
+    log.file.sync_all()?;
+    Ok(())
+}
+
+/// Check that the audit log can still be written
+pub fn check() -> Result<(), Box<dyn Error>> {
+    if AUDIT_LOG.is_poisoned() {
+        return Err(POISONED_AUDIT_LOG.into());
+    }
+    Ok(())
+}
+
+/// Record an event in the audit log
+/// A failure to write the record is logged but doesn't stop the action
+/// # Arguments
+/// * `u` - connected user performing the action
+/// * `action` - name of the action
+/// * `target` - user or session targeted by the action, if any
+/// * `error` - error message sent to the client, None if the action succeeded
+/// # Returns
+/// * `AuditOutcome` - The outcome recorded
+pub fn record(u: &ConnectedUser, action: &str, target: Option<&str>, error: Option<&str>) -> AuditOutcome {
+    trace!("Audit {}", action);
+
+    let outcome = match error {
+        None => AuditOutcome::Success,
+        Some(PERMISSION_DENIED) => AuditOutcome::Denied,
+        Some(_) => AuditOutcome::Failure,
+    };
+    let record = AuditRecord {
+        seq: 0,
+        timestamp: now_timestamp(),
+        actor: u.logged_username().map(str::to_string),
+        action: action.to_string(),
+        target: target.map(str::to_string),
+        outcome,
+        reason: error.map(str::to_string),
+        peer_ip: u.peer_ip().to_string(),
+        prev_hash: String::new(),
+        hash: String::new(),
+    };
+
+    match AUDIT_LOG.lock() {
+        Ok(mut log) => {
+            if let Err(e) = log.append(record) {
+                error!("An error occurred writing the audit log: {}", e);
+            }
+        }
+        Err(_) => error!("The event {} can't be recorded: {}", action, POISONED_AUDIT_LOG),
+    }
+    outcome
+}
+
+/// Verify the hash chain of an audit log
+/// # Arguments
+/// * `path` - path of the audit log
+/// # Returns
+/// * `Result<(u64, String), String>` - The number of records and the last hash, or the first broken record
+pub fn verify_audit_log(path: &str) -> Result<(u64, String), String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("Can't read {}: {}", path, e))?;
+
+    let mut count = 0;
+    let mut last_hash = GENESIS_HASH.to_string();
+    for (i, line) in content.lines().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+        let line_number = i + 1;
+        let record: AuditRecord = serde_json::from_str(line)
+            .map_err(|e| format!("Line {}: invalid record: {}", line_number, e))?;
+
+        if record.seq != count {
+            return Err(format!("Line {}: expected record {} but found {}, records are missing",
+                               line_number, count, record.seq));
+        }
+        if record.prev_hash != last_hash {
+            return Err(format!("Line {}: the previous hash doesn't match, \
+            a record has been removed or modified", line_number));
+        }
+        let hash = record.compute_hash().map_err(|e| e.to_string())?;
+        if record.hash != hash {
+            return Err(format!("Line {}: the hash doesn't match, the record has been modified",
+                               line_number));
+        }
+
+        count += 1;
+        last_hash = record.hash;
+    }
+
+    Ok((count, last_hash))
+}
+
+/// Search the audit log
+/// # Arguments
+/// * `query` - filters and page to send, already validated
+/// # Returns
+/// * `Result<AuditPage, Box<dyn Error>>` - The requested page of the matching events, the most recent first
+pub fn query_audit_log(query: &AuditQuery) -> Result<AuditPage, Box<dyn Error>> {
+    // Only the records complete when the query starts are read, the actions are recorded meanwhile
+    let length = AUDIT_LOG.lock().map_err(|_| POISONED_AUDIT_LOG)?.file.metadata()?.len();
+    let reader = BufReader::new(File::open(&CONFIG.audit_log_path)?.take(length));
+
+    let mut matching = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: AuditRecord = serde_json::from_str(&line)?;
+        if query.matches(&record) {
+            matching.push(record);
+        }
+    }
+
+    let total = matching.len();
+    let page_count = total.div_ceil(AUDIT_PAGE_SIZE).max(1) as u32;
+    let events = matching
+        .into_iter()
+        .rev()
+        .skip((query.page.saturating_sub(1) as usize).saturating_mul(AUDIT_PAGE_SIZE))
+        .take(AUDIT_PAGE_SIZE)
+        .map(AuditEvent::from)
+        .collect();
+
+    Ok(AuditPage { events, page: query.page, page_count, total: total as u64 })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    // A valid chain of records, as written by the audit log
+    fn chain(length: u64) -> Vec<AuditRecord> {
+        let mut records = Vec::new();
+        let mut last_hash = GENESIS_HASH.to_string();
+        for seq in 0..length {
+            let mut record = AuditRecord {
+                seq,
+                timestamp: 1700000000 + seq,
+                actor: Some("hr".to_string()),
+                action: "change_phone".to_string(),
+                target: Some(format!("user{}", seq)),
+                outcome: AuditOutcome::Success,
+                reason: None,
+                peer_ip: "127.0.0.1".to_string(),
+                prev_hash: last_hash,
+                hash: String::new(),
+            };
+            record.hash = record.compute_hash().unwrap();
+            last_hash = record.hash.clone();
+            records.push(record);
+        }
+        records
+    }
+
+    fn verify(name: &str, records: &[AuditRecord]) -> Result<(u64, String), String> {
+        let path = env::temp_dir().join(format!("audit-test-{}-{}.jsonl", name, std::process::id()));
+        let lines: Vec<String> = records.iter().map(|record| serde_json::to_string(record).unwrap()).collect();
+        fs::write(&path, lines.join("\n") + "\n").unwrap();
+        let res = verify_audit_log(&path.to_string_lossy());
+        fs::remove_file(&path).unwrap();
+        res
+    }
+
+    #[test]
+    fn test_verify_audit_log() {
+        let records = chain(3);
+        assert_eq!(verify("valid", &records), Ok((3, records[2].hash.clone())));
+        assert_eq!(verify("empty", &[]), Ok((0, GENESIS_HASH.to_string())));
+
+        // A modified record doesn't match its hash
+        let mut modified = records.clone();
+        modified[1].outcome = AuditOutcome::Denied;
+        assert!(verify("modified", &modified).unwrap_err().starts_with("Line 2: the hash doesn't match"));
+
+        // A modified record whose hash is computed again breaks the chain at the next record
+        modified[1].hash = modified[1].compute_hash().unwrap();
+        assert!(verify("rehashed", &modified).unwrap_err().starts_with("Line 3: the previous hash doesn't match"));
+
+        // A removed record leaves a gap in the sequence numbers
+        let removed = [records[0].clone(), records[2].clone()];
+        assert!(verify("removed", &removed).unwrap_err().starts_with("Line 2: expected record 1 but found 2"));
+
+        // A record that isn't JSON can't be checked
+        let path = env::temp_dir().join(format!("audit-test-invalid-{}.jsonl", std::process::id()));
+        fs::write(&path, format!("{}\n{{\"seq\":1\n", serde_json::to_string(&records[0]).unwrap())).unwrap();
+        assert!(verify_audit_log(&path.to_string_lossy()).unwrap_err().starts_with("Line 2: invalid record"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_open_cut_audit_log() {
+        let records = chain(3);
+        let lines: Vec<String> = records.iter().map(|record| serde_json::to_string(record).unwrap()).collect();
+        let path = env::temp_dir().join(format!("audit-test-cut-{}.jsonl", std::process::id()));
+        let path = path.to_string_lossy().to_string();
+        let incomplete = format!("{}.{}", path, INCOMPLETE_EXTENSION);
+
+        // The cut record is moved aside and the chain continues from the record before it
+        let valid = format!("{}\n{}\n", lines[0], lines[1]);
+        fs::write(&path, format!("{}{}", valid, &lines[2][..40])).unwrap();
+        let log = AuditLog::open(&path).unwrap();
+        assert_eq!((log.next_seq, log.last_hash.as_str()), (2, records[1].hash.as_str()));
+        assert_eq!(fs::read_to_string(&path).unwrap(), valid);
+        assert_eq!(fs::read_to_string(&incomplete).unwrap(), format!("{}\n", &lines[2][..40]));
+        assert_eq!(verify_audit_log(&path), Ok((2, records[1].hash.clone())));
+
+        // A complete record whose new line is missing is kept
+        fs::write(&path, format!("{}{}", valid, lines[2])).unwrap();
+        let log = AuditLog::open(&path).unwrap();
+        assert_eq!((log.next_seq, log.last_hash.as_str()), (3, records[2].hash.as_str()));
+        assert_eq!(verify_audit_log(&path), Ok((3, records[2].hash.clone())));
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&incomplete).unwrap();
+    }
+}
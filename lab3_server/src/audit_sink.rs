@@ -0,0 +1,365 @@
+/// This file is used to export the audit records to a SIEM
+/// Each record written to the audit log is also given to the sinks selected in the config:
+/// a file of Common Event Format (CEF) lines or RFC 5424 syslog messages sent to an UDP or Unix socket
+use crate::audit::{AuditOutcome, AuditRecord};
+use crate::time_tools::format_rfc3339;
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::net::UdpSocket;
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+use std::process;
+use std::str::FromStr;
+
+const PRODUCT_VENDOR: &str = "HEIG-VD";
+const PRODUCT_NAME: &str = "RESIGN";
+const PRODUCT_VERSION: &str = env!("CARGO_PKG_VERSION");
+const APP_NAME: &str = env!("CARGO_PKG_NAME");
+// Enterprise number reserved for documentation by RFC 5612
+const SYSLOG_SD_ID: &str = "audit@32473";
+// Security/authorization messages
+const SYSLOG_FACILITY_AUTHPRIV: u8 = 10;
+const MAX_SYSLOG_MESSAGE_LENGTH: usize = 65507;
+
+/// A destination of the audit records besides the audit log
+pub trait AuditSink: Send {
+    /// Export a record, already written to the audit log
+    fn send(&mut self, record: &AuditRecord) -> Result<(), Box<dyn Error>>;
+}
+
+/// The kinds of sinks that can be selected in the config
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AuditSinkKind {
+    Cef,
+    Syslog,
+}
+
+/// Address of a syslog collector, `udp://<host>:<port>` or `unix://<path>`
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum SyslogAddress {
+    Udp(String),
+    Unix(PathBuf),
+}
+
+impl FromStr for AuditSinkKind {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "cef" => Ok(AuditSinkKind::Cef),
+            "syslog" => Ok(AuditSinkKind::Syslog),
+            _ => Err(format!("Invalid audit sink: {}", s).into()),
+        }
+    }
+}
+
+impl FromStr for SyslogAddress {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(address) = s.strip_prefix("udp://") {
+            Ok(SyslogAddress::Udp(address.to_string()))
+        } else if let Some(path) = s.strip_prefix("unix://") {
+            Ok(SyslogAddress::Unix(PathBuf::from(path)))
+        } else {
+            Err(format!("Invalid syslog address: {}", s).into())
+        }
+    }
+}
+
+/// Parse the list of sinks from the config
+/// # Arguments
+/// * `value` - comma-separated list of sinks, e.g. `cef,syslog`
+/// # Returns
+/// * `Result<Vec<AuditSinkKind>, Box<dyn Error>>` - The selected sinks
+pub fn parse_audit_sinks(value: &str) -> Result<Vec<AuditSinkKind>, Box<dyn Error>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|sink| !sink.is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+/// Writes the records as CEF lines, one per line
+pub struct CefFileSink {
+    file: File,
+}
+
+impl CefFileSink {
+    pub fn open(path: &str) -> Result<CefFileSink, Box<dyn Error>> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(CefFileSink { file })
+    }
+}
+
+impl AuditSink for CefFileSink {
+    fn send(&mut self, record: &AuditRecord) -> Result<(), Box<dyn Error>> {
+        writeln!(self.file, "{}", format_cef(record))?;
+        Ok(())
+    }
+}
+
+enum SyslogSocket {
+    Udp(UdpSocket),
+    Unix(UnixDatagram, PathBuf),
+}
+
+/// Sends the records as RFC 5424 syslog messages over UDP or an Unix datagram socket
+pub struct SyslogSink {
+    socket: SyslogSocket,
+    hostname: String,
+}
+
+impl SyslogSink {
+    pub fn connect(address: &SyslogAddress) -> Result<SyslogSink, Box<dyn Error>> {
+        let socket = match address {
+            SyslogAddress::Udp(address) => {
+                let socket = UdpSocket::bind("0.0.0.0:0")?;
+                socket.connect(address)?;
+                SyslogSocket::Udp(socket)
+            }
+            SyslogAddress::Unix(path) => SyslogSocket::Unix(UnixDatagram::unbound()?, path.clone()),
+        };
+        Ok(SyslogSink { socket, hostname: hostname() })
+    }
+}
+
+impl AuditSink for SyslogSink {
+    fn send(&mut self, record: &AuditRecord) -> Result<(), Box<dyn Error>> {
+        let mut message = format_syslog(record, &self.hostname).into_bytes();
+        message.truncate(MAX_SYSLOG_MESSAGE_LENGTH);
+        match &self.socket {
+            SyslogSocket::Udp(socket) => socket.send(&message)?,
+            SyslogSocket::Unix(socket, path) => socket.send_to(&message, path)?,
+        };
+        Ok(())
+    }
+}
+
+// The hostname is optional in syslog messages
+fn hostname() -> String {
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .map(|name| name.trim().to_string())
+        .ok()
+        .filter(|name| !name.is_empty() && name.chars().all(|c| c.is_ascii_graphic()))
+        .unwrap_or_else(|| "-".to_string())
+}
+
+fn cef_severity(outcome: AuditOutcome) -> u8 {
+    match outcome {
+        AuditOutcome::Success => 3,
+        AuditOutcome::Failure => 6,
+        AuditOutcome::Denied => 8,
+    }
+}
+
+fn syslog_severity(outcome: AuditOutcome) -> u8 {
+    match outcome {
+        AuditOutcome::Success => 6, // informational
+        AuditOutcome::Failure => 5, // notice
+        AuditOutcome::Denied => 4, // warning
+    }
+}
+
+fn outcome_string(outcome: AuditOutcome) -> &'static str {
+    match outcome {
+        AuditOutcome::Success => "success",
+        AuditOutcome::Failure => "failure",
+        AuditOutcome::Denied => "denied",
+    }
+}
+
+// The pipes and backslashes of the header are escaped
+fn escape_cef_header(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('|', "\\|")
+}
+
+// The equal signs, backslashes and new lines of the extension values are escaped
+fn escape_cef_extension(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('=', "\\=")
+        .replace('\r', "\\r")
+        .replace('\n', "\\n")
+}
+
+// The quotes, backslashes and closing brackets of the parameter values are escaped
+fn escape_sd_param(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace(']', "\\]")
+}
+
+/// Format a record as a CEF line
+/// # Arguments
+/// * `record` - record to format
+/// # Returns
+/// * `String` - `CEF:0|vendor|product|version|action|name|severity|extension`
+pub fn format_cef(record: &AuditRecord) -> String {
+    let mut extension = vec![
+        format!("rt={}", record.timestamp * 1000),
+        format!("outcome={}", outcome_string(record.outcome)),
+        format!("src={}", escape_cef_extension(&record.peer_ip)),
+        format!("cn1Label=seq cn1={}", record.seq),
+    ];
+    if let Some(actor) = &record.actor {
+        extension.push(format!("suser={}", escape_cef_extension(actor)));
+    }
+    if let Some(target) = &record.target {
+        extension.push(format!("duser={}", escape_cef_extension(target)));
+    }
+    if let Some(reason) = &record.reason {
+        extension.push(format!("reason={}", escape_cef_extension(reason)));
+    }
+    extension.push(format!("cs1Label=hash cs1={}", record.hash));
+
+    format!("CEF:0|{}|{}|{}|{}|{} {}|{}|{}",
+            escape_cef_header(PRODUCT_VENDOR),
+            escape_cef_header(PRODUCT_NAME),
+            escape_cef_header(PRODUCT_VERSION),
+            escape_cef_header(&record.action),
+            escape_cef_header(&record.action),
+            outcome_string(record.outcome),
+            cef_severity(record.outcome),
+            extension.join(" "))
+}
+
+/// Format a record as a RFC 5424 syslog message
+/// # Arguments
+/// * `record` - record to format
+/// * `hostname` - name of the server, `-` if unknown
+/// # Returns
+/// * `String` - The message with the record as structured data
+pub fn format_syslog(record: &AuditRecord, hostname: &str) -> String {
+    let priority = SYSLOG_FACILITY_AUTHPRIV * 8 + syslog_severity(record.outcome);
+    let actor = record.actor.as_deref().unwrap_or("anonymous");
+
+    let mut params = vec![
+        format!("seq=\"{}\"", record.seq),
+        format!("outcome=\"{}\"", outcome_string(record.outcome)),
+        format!("peerIp=\"{}\"", escape_sd_param(&record.peer_ip)),
+    ];
+    if let Some(actor) = &record.actor {
+        params.push(format!("actor=\"{}\"", escape_sd_param(actor)));
+    }
+    if let Some(target) = &record.target {
+        params.push(format!("target=\"{}\"", escape_sd_param(target)));
+    }
+    if let Some(reason) = &record.reason {
+        params.push(format!("reason=\"{}\"", escape_sd_param(reason)));
+    }
+    params.push(format!("hash=\"{}\"", record.hash));
+
+    format!("<{}>1 {} {} {} {} {} [{} {}] {} {} by {}",
+            priority,
+            format_rfc3339(record.timestamp),
+            hostname,
+            APP_NAME,
+            process::id(),
+            record.action,
+            SYSLOG_SD_ID,
+            params.join(" "),
+            record.action,
+            outcome_string(record.outcome),
+            actor)
+}
+
+/// Open the sinks selected in the config
+/// # Arguments
+/// * `kinds` - sinks to open
+/// * `cef_path` - path of the CEF file
+/// * `syslog_address` - address of the syslog collector
+/// # Returns
+/// * `Result<Vec<Box<dyn AuditSink>>, Box<dyn Error>>` - The opened sinks
+pub fn open_sinks(kinds: &[AuditSinkKind], cef_path: &str, syslog_address: &SyslogAddress)
+    -> Result<Vec<Box<dyn AuditSink>>, Box<dyn Error>> {
+    kinds
+        .iter()
+        .map(|kind| -> Result<Box<dyn AuditSink>, Box<dyn Error>> {
+            match kind {
+                AuditSinkKind::Cef => Ok(Box::new(CefFileSink::open(cef_path)?)),
+                AuditSinkKind::Syslog => Ok(Box::new(SyslogSink::connect(syslog_address)?)),
+            }
+        })
+        .collect()
+}
+
+/// Print the syslog messages received on an address, to check the output of the syslog sink
+/// # Arguments
+/// * `address` - address to listen on
+/// # Returns
+/// * `Result<(), Box<dyn Error>>` - An error if the address can't be listened on, never returns otherwise
+pub fn listen(address: &SyslogAddress) -> Result<(), Box<dyn Error>> {
+    let mut buffer = vec![0u8; MAX_SYSLOG_MESSAGE_LENGTH];
+    match address {
+        SyslogAddress::Udp(address) => {
+            let socket = UdpSocket::bind(address)?;
+            println!("Listening for syslog messages on udp://{}", address);
+            loop {
+                let (len, peer) = socket.recv_from(&mut buffer)?;
+                println!("{} {}", peer, String::from_utf8_lossy(&buffer[..len]));
+            }
+        }
+        SyslogAddress::Unix(path) => {
+            let socket = UnixDatagram::bind(path)?;
+            println!("Listening for syslog messages on unix://{}", path.display());
+            loop {
+                let len = socket.recv(&mut buffer)?;
+                println!("{}", String::from_utf8_lossy(&buffer[..len]));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(actor: Option<&str>, action: &str, target: Option<&str>, outcome: AuditOutcome,
+              reason: Option<&str>) -> AuditRecord {
+        AuditRecord {
+            seq: 7,
+            timestamp: 1700000000,
+            actor: actor.map(str::to_string),
+            action: action.to_string(),
+            target: target.map(str::to_string),
+            outcome,
+            reason: reason.map(str::to_string),
+            peer_ip: "10.0.0.1".to_string(),
+            prev_hash: "0".repeat(64),
+            hash: "ab12".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_format_cef() {
+        let denied = record(Some("bob=x\\y\nz"), "change|phone\\", Some("alice"), AuditOutcome::Denied, Some("a|b"));
+        assert_eq!(format_cef(&denied), format!(
+            "CEF:0|HEIG-VD|RESIGN|{}|change\\|phone\\\\|change\\|phone\\\\ denied|8|rt=1700000000000 outcome=denied \
+            src=10.0.0.1 cn1Label=seq cn1=7 suser=bob\\=x\\\\y\\nz duser=alice reason=a|b cs1Label=hash cs1=ab12",
+            PRODUCT_VERSION));
+
+        // The fields that aren't set are left out
+        let anonymous = record(None, "login", None, AuditOutcome::Success, None);
+        assert_eq!(format_cef(&anonymous), format!(
+            "CEF:0|HEIG-VD|RESIGN|{}|login|login success|3|rt=1700000000000 outcome=success \
+            src=10.0.0.1 cn1Label=seq cn1=7 cs1Label=hash cs1=ab12",
+            PRODUCT_VERSION));
+    }
+
+    #[test]
+    fn test_format_syslog() {
+        let failure = record(Some("hr"), "change_phone", Some("ali\"ce]"), AuditOutcome::Failure, Some("a\\b"));
+        assert_eq!(format_syslog(&failure, "server"), format!(
+            "<85>1 2023-11-14T22:13:20Z server lab3_server {} change_phone [audit@32473 seq=\"7\" outcome=\"failure\" \
+            peerIp=\"10.0.0.1\" actor=\"hr\" target=\"ali\\\"ce\\]\" reason=\"a\\\\b\" hash=\"ab12\"] change_phone failure by hr",
+            process::id()));
+
+        // The priority is the authpriv facility with the severity of the outcome
+        let anonymous = record(None, "login", None, AuditOutcome::Denied, None);
+        assert_eq!(format_syslog(&anonymous, "-"), format!(
+            "<84>1 2023-11-14T22:13:20Z - lab3_server {} login [audit@32473 seq=\"7\" outcome=\"denied\" \
+            peerIp=\"10.0.0.1\" hash=\"ab12\"] login denied by anonymous",
+            process::id()));
+    }
+}
@@ -0,0 +1,265 @@
+/// This file is used to back up the user accounts
+/// A backup is a consistent copy of the database in `BACKUP_DIR`, named after the time it was made,
+/// with a checksum file in the `sha256sum` format next to it. Backups are made every `BACKUP_INTERVAL`
+/// seconds and on demand by HR, and only the `BACKUP_RETENTION` most recent ones are kept
+use crate::database::{journal_path, load_ron_users, DB_PATH};
+use crate::env_reader::CONFIG;
+use crate::hex_tools::to_hex;
+use crate::sqlite_store::SqliteUserStore;
+use crate::time_tools::{format_rfc3339, now_timestamp};
+use crate::user_store::{UserStore, UserStoreKind};
+use log::{error, info};
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::fs::{self, File, TryLockError};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const BACKUP_PREFIX: &str = "db-";
+const CHECKSUM_EXTENSION: &str = "sha256";
+const NAME_POLL_INTERVAL: Duration = Duration::from_millis(100);
+const POISONED_BACKUPS: &str = "The backups are unusable after a panic";
+const LOCK_EXTENSION: &str = "lock";
+const BEFORE_RESTORE_EXTENSION: &str = "before-restore";
+
+// A single backup is made at a time, e.g. when HR asks for one during a scheduled backup
+static BACKUP_LOCK: Mutex<()> = Mutex::new(());
+
+fn extension(kind: UserStoreKind) -> &'static str {
+    match kind {
+        UserStoreKind::Ron => "ron",
+        UserStoreKind::Sqlite => "sqlite",
+    }
+}
+
+fn checksum_path(path: &Path) -> PathBuf {
+    let mut checksum_path = path.as_os_str().to_owned();
+    checksum_path.push(format!(".{}", CHECKSUM_EXTENSION));
+    PathBuf::from(checksum_path)
+}
+
+fn checksum(path: &Path) -> Result<String, Box<dyn Error>> {
+    Ok(to_hex(&Sha256::digest(fs::read(path)?)))
+}
+
+/// Make a backup of the database and remove the oldest backups beyond the retention count
+/// # Arguments
+/// * `store` - store of the user accounts
+/// # Returns
+/// * `Result<String, Box<dyn Error>>` - The file name of the backup
+pub fn create_backup(store: &dyn UserStore) -> Result<String, Box<dyn Error>> {
+    let _lock = BACKUP_LOCK.lock().map_err(|_| POISONED_BACKUPS)?;
+    let (name, path) = new_backup_path()?;
+    store.backup(&path.to_string_lossy())?;
+    complete_backup(&name, &path)?;
+    Ok(name)
+}
+
+/// Back up a copy of a database file, e.g. before the server changes its format
+/// # Arguments
+/// * `path` - path of the database file, which must not be in use
+/// # Returns
+/// * `Result<String, Box<dyn Error>>` - The file name of the backup
+pub fn back_up_file(path: &str) -> Result<String, Box<dyn Error>> {
+    let _lock = BACKUP_LOCK.lock().map_err(|_| POISONED_BACKUPS)?;
+    let (name, backup) = new_backup_path()?;
+    fs::copy(path, &backup)?;
+    File::open(&backup)?.sync_all()?;
+    complete_backup(&name, &backup)?;
+    Ok(name)
+}
+
+// The backups are named after the second they are made in, so their names sort by age
+fn new_backup_path() -> Result<(String, PathBuf), Box<dyn Error>> {
+    fs::create_dir_all(&CONFIG.backup_dir)?;
+    loop {
+        let time = format_rfc3339(now_timestamp()).replace(':', "-");
+        let name = format!("{}{}.{}", BACKUP_PREFIX, time, extension(CONFIG.user_store));
+        let path = Path::new(&CONFIG.backup_dir).join(&name);
+        if !path.exists() {
+            return Ok((name, path));
+        }
+        thread::sleep(NAME_POLL_INTERVAL);
+    }
+}
+
+// The checksum file is written last, a backup without one is incomplete
+fn complete_backup(name: &str, path: &Path) -> Result<(), Box<dyn Error>> {
+    let mut checksum_file = File::create(checksum_path(path))?;
+    writeln!(checksum_file, "{}  {}", checksum(path)?, name)?;
+    checksum_file.sync_all()?;
+    info!("Backup {} created", name);
+    prune_backups()
+}
+
+// Backups of the store of the config, the oldest first
+fn list_backups() -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let suffix = format!(".{}", extension(CONFIG.user_store));
+    let mut backups = Vec::new();
+    for entry in fs::read_dir(&CONFIG.backup_dir)? {
+        let name = entry?.file_name().to_string_lossy().into_owned();
+        if name.starts_with(BACKUP_PREFIX) && name.ends_with(&suffix) {
+            backups.push(Path::new(&CONFIG.backup_dir).join(name));
+        }
+    }
+    backups.sort();
+    Ok(backups)
+}
+
+// Remove the oldest backups with their checksum files
+fn prune_backups() -> Result<(), Box<dyn Error>> {
+    let mut backups = list_backups()?;
+    let excess = backups.len().saturating_sub(CONFIG.backup_retention);
+    for path in backups.drain(..excess) {
+        fs::remove_file(&path)?;
+        match fs::remove_file(checksum_path(&path)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        info!("Backup {} removed", path.display());
+    }
+    Ok(())
+}
+
+/// Start making backups every `BACKUP_INTERVAL` seconds, unless it is 0
+/// # Arguments
+/// * `store` - store of the user accounts
+pub fn init(store: Arc<dyn UserStore>) {
+    if CONFIG.backup_interval == 0 {
+        return;
+    }
+    let interval = Duration::from_secs(CONFIG.backup_interval);
+    info!("Backups scheduled every {} seconds in {}", CONFIG.backup_interval, CONFIG.backup_dir);
+
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        if let Err(e) = create_backup(&*store) {
+            error!("An error occurred making a scheduled backup: {}", e);
+        }
+    });
+}
+
+// Path of the database of the config
+fn database_path(kind: UserStoreKind) -> String {
+    match kind {
+        UserStoreKind::Ron => DB_PATH.to_string(),
+        UserStoreKind::Sqlite => CONFIG.sqlite_path.clone(),
+    }
+}
+
+/// Lock the database of the config for as long as the returned file is open, so that it isn't
+/// restored while the server uses it. The lock is released by the system if the server crashes
+/// # Returns
+/// * `Result<File, Box<dyn Error>>` - The lock file, to keep open
+pub fn lock_database() -> Result<File, Box<dyn Error>> {
+    lock_database_file(&database_path(CONFIG.user_store))
+}
+
+/// Lock a database file like the server locks the database of the config, e.g. a RON file to import
+/// # Arguments
+/// * `path` - path of the database file
+/// # Returns
+/// * `Result<File, Box<dyn Error>>` - The lock file, to keep open
+pub fn lock_database_file(path: &str) -> Result<File, Box<dyn Error>> {
+    let path = format!("{}.{}", path, LOCK_EXTENSION);
+    let file = File::options().create(true).truncate(false).write(true).open(&path)?;
+    match file.try_lock() {
+        Ok(()) => Ok(file),
+        Err(TryLockError::WouldBlock) => Err(format!("The database is in use, {} is locked by a running server", path).into()),
+        Err(TryLockError::Error(e)) => Err(e.into()),
+    }
+}
+
+/// Check that a backup matches the checksum file next to it
+/// # Arguments
+/// * `path` - path of the backup
+pub fn verify_checksum(path: &Path) -> Result<(), Box<dyn Error>> {
+    let content = fs::read_to_string(checksum_path(path))
+        .map_err(|e| format!("The checksum file of the backup can't be read: {}", e))?;
+    let expected = content.split_whitespace().next().ok_or("The checksum file of the backup is empty")?;
+    if !expected.eq_ignore_ascii_case(&checksum(path)?) {
+        return Err("The backup doesn't match its checksum, it is corrupted".into());
+    }
+    Ok(())
+}
+
+// Load every account of a copy of a backup, the schema of a SQLite backup is migrated
+fn load_backup(kind: UserStoreKind, path: &str) -> Result<usize, Box<dyn Error>> {
+    match kind {
+        UserStoreKind::Ron => Ok(load_ron_users(path)?.len()),
+        UserStoreKind::Sqlite => {
+            let store = SqliteUserStore::open(path)?;
+            store.check()?;
+            Ok(store.list()?.len())
+        }
+    }
+}
+
+/// Replace the database of the config by a backup, the server must be stopped
+/// The backup is checked, then copied next to the database and loaded before it replaces the database.
+/// The journals of the database are moved aside with a `.before-restore` extension
+/// # Arguments
+/// * `path` - path of the backup
+/// # Returns
+/// * `Result<(String, usize), Box<dyn Error>>` - The path of the database and the number of accounts restored
+pub fn restore_backup(path: &str) -> Result<(String, usize), Box<dyn Error>> {
+    let kind = CONFIG.user_store;
+    let backup = Path::new(path);
+    if backup.extension().and_then(|extension| extension.to_str()) != Some(extension(kind)) {
+        return Err(format!("Only a .{} backup can be restored to the store of the config", extension(kind)).into());
+    }
+    let _lock = lock_database()?;
+    verify_checksum(backup)?;
+
+    let target = database_path(kind);
+    let restored = format!("{}.restore", target);
+    fs::copy(backup, &restored)?;
+    let count = match load_backup(kind, &restored) {
+        Ok(count) => count,
+        Err(e) => {
+            fs::remove_file(&restored)?;
+            return Err(format!("The backup can't be loaded: {}", e).into());
+        }
+    };
+
+    // The changes not written to the current database yet don't apply to the backup, they are kept
+    // aside in case the wrong backup is restored
+    let stale = match kind {
+        UserStoreKind::Ron => vec![journal_path(&target)],
+        UserStoreKind::Sqlite => vec![format!("{}-journal", target), format!("{}-wal", target)],
+    };
+    for stale in stale {
+        match fs::rename(&stale, format!("{}.{}", stale, BEFORE_RESTORE_EXTENSION)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+    }
+    fs::rename(&restored, &target)?;
+    let dir = Path::new(&target).parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    File::open(dir)?.sync_all()?;
+    Ok((target, count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_verify_checksum() {
+        let path = env::temp_dir().join(format!("backup-test-{}.ron", std::process::id()));
+        fs::write(&path, b"(data: {})").unwrap();
+        fs::write(checksum_path(&path), format!("{}  backup.ron\n", checksum(&path).unwrap())).unwrap();
+        assert!(verify_checksum(&path).is_ok());
+
+        fs::write(&path, b"(data: {}) ").unwrap();
+        assert!(verify_checksum(&path).is_err());
+
+        fs::remove_file(checksum_path(&path)).unwrap();
+        assert!(verify_checksum(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
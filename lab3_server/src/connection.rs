@@ -2,15 +2,76 @@ use native_tls::TlsStream;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 use std::error::Error;
-use std::net::TcpStream;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+
+// The tests talk to the actions without TLS
+enum Stream {
+    Tls(TlsStream<TcpStream>),
+    #[cfg(test)]
+    Plain(TcpStream),
+}
+
+impl Stream {
+    fn socket(&self) -> &TcpStream {
+        match self {
+            Stream::Tls(stream) => stream.get_ref(),
+            #[cfg(test)]
+            Stream::Plain(stream) => stream,
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tls(stream) => stream.read(buf),
+            #[cfg(test)]
+            Stream::Plain(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tls(stream) => stream.write(buf),
+            #[cfg(test)]
+            Stream::Plain(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tls(stream) => stream.flush(),
+            #[cfg(test)]
+            Stream::Plain(stream) => stream.flush(),
+        }
+    }
+}
 
 pub struct Connection {
-    stream: TlsStream<TcpStream>,
+    stream: Stream,
 }
 
 impl Connection {
     pub fn new(stream: TlsStream<TcpStream>) -> Connection {
-        Connection { stream }
+        Connection { stream: Stream::Tls(stream) }
+    }
+
+    /// Connection without TLS, to call the actions in the tests
+    #[cfg(test)]
+    pub fn plain(stream: TcpStream) -> Connection {
+        Connection { stream: Stream::Plain(stream) }
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.stream.socket().peer_addr()
+    }
+
+    /// Clone the underlying socket, used to close the connection from another thread
+    pub fn try_clone_socket(&self) -> io::Result<TcpStream> {
+        self.stream.socket().try_clone()
     }
 
     pub fn send<T>(&mut self, o: &T) -> Result<(), Box<dyn Error>>
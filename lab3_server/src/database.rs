@@ -1,92 +1,336 @@
-/// This file is used to store and retrieve user accounts from the database
+/// This file is used to store and retrieve user accounts from the RON database
 ///
 /// Tasks: - Log stuff whenever required
 ///        - Potential improvements
 use crate::user::{UserAccount, UserRole};
-use crate::hashing_tools::new_hash_password;
-use crate::env_reader::read_env_file;
-use lazy_static::lazy_static;
-use rustbreak::{deser::Ron, FileDatabase};
+use crate::hashing_tools::new_password_hash;
+use crate::env_reader::CONFIG;
+use crate::metrics::observe_db_save;
+use crate::db_crypto::{config_key_source, is_encrypted, DbKey, KeySource};
+use crate::db_schema::{migrate, schema_version, SCHEMA_VERSION};
+use crate::backup::back_up_file;
+use crate::journal::{replay, Journal, JournalEntry};
+use crate::user_store::{begin_write, TransactionTask, UserStore, UserTransaction, Users};
+use rustbreak::{deser::Ron, DeSerializer};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
 use std::error::Error;
-use log::{error, info};
+use std::fs::{self, File};
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+use std::sync::{Mutex, RwLock};
+use std::time::Instant;
+use log::info;
 
-lazy_static! {
-    // No log cause the server crashes if it doesn't work
-    static ref DB: FileDatabase<Database, Ron> =
-        FileDatabase::load_from_path_or_default("db.ron").unwrap();
-}
+pub const DB_PATH: &str = "db.ron";
 
+/// Content of the RON file
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Database {
-    data: HashMap<String, UserAccount>,
+    version: u32,
+    data: Users,
+}
+
+/// Store of the user accounts in a RON snapshot and a journal of the changes made since
+/// The journal is compacted into a new snapshot when the server starts and stops,
+/// and after `JOURNAL_MAX_ENTRIES` changes
+pub struct RonUserStore {
+    users: RwLock<Users>,
+    journal: Mutex<Journal>,
+    path: String,
+    key: Option<DbKey>,
+}
+
+const POISONED_STORE: &str = "The RON database is unusable after a panic";
+const MISSING_KEY: &str = "The database is encrypted, DB_KEY_PATH or DB_PASSPHRASE must be set";
+const NOT_ENCRYPTED: &str = "The database isn't encrypted yet, encrypt it with the rotate-key command";
+
+/// Path of the journal of a RON file
+pub fn journal_path(path: &str) -> String {
+    format!("{}.journal", path)
+}
+
+// Load the key of the database, a passphrase is derived with the salt of the snapshot if it is encrypted
+fn load_key(path: &str, source: Option<&KeySource>) -> Result<Option<DbKey>, Box<dyn Error>> {
+    let source = match source {
+        Some(source) => source,
+        None => return Ok(None),
+    };
+    let snapshot = match fs::read(path) {
+        Ok(data) => Some(data),
+        Err(e) if e.kind() == ErrorKind::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+    let encrypted = snapshot.filter(|data| is_encrypted(data));
+    Ok(Some(DbKey::load(source, encrypted.as_deref())?))
 }
 
-impl Database {
-    pub fn insert(user: &UserAccount) -> Result<(), Box<dyn Error>> {
-        DB.write(|db| db.data.insert(user.username().to_string(), user.clone()))?;
-        Ok(DB.save()?)
+// Read the snapshot in RON and its generation, which only encrypted snapshots keep
+fn read_snapshot(path: &str, key: Option<&DbKey>) -> Result<(Vec<u8>, u64), Box<dyn Error>> {
+    let data = fs::read(path)?;
+    match (is_encrypted(&data), key) {
+        (true, Some(key)) => key.decrypt_snapshot(&data),
+        (true, None) => Err(MISSING_KEY.into()),
+        (false, Some(_)) => Err(NOT_ENCRYPTED.into()),
+        (false, None) => Ok((data, 0)),
     }
+}
+
+// Read the snapshot, migrated to the current schema version, and apply the journal
+// The generation of the snapshot to write next is returned with the accounts, it only changes when the
+// journal had changes, so the changes of a former generation left by a crash can still be recognized
+fn load(path: &str, key: Option<&DbKey>) -> Result<(Users, u64), Box<dyn Error>> {
+    let (data, generation) = read_snapshot(path, key)?;
+    let data = migrate(data)?;
+    let db: Database = Ron.deserialize(&data[..])?;
+    let mut users = db.data;
+    let applied = replay(&journal_path(path), &mut users, key, generation)?;
+    if applied == 0 {
+        return Ok((users, generation));
+    }
+    info!("{} changes replayed from the journal of {}", applied, path);
+    Ok((users, generation + 1))
+}
 
-    pub fn get(username: &str) -> Result<Option<UserAccount>, Box<dyn Error>> {
-        Ok(match DB.borrow_data()?.data.get(username) {
-            Some(user) => Some(user.clone()),
-            None => None,
+// Write the accounts to a temporary file renamed over the snapshot, the snapshot is never partly written
+fn write_snapshot(path: &str, users: &Users, key: Option<&DbKey>, generation: u64) -> Result<(), Box<dyn Error>> {
+    let start = Instant::now();
+    let content = Ron.serialize(&Database { version: SCHEMA_VERSION, data: users.clone() })?;
+    let content = match key {
+        Some(key) => key.encrypt_snapshot(&content, generation)?,
+        None => content,
+    };
+    let tmp_path = format!("{}.tmp", path);
+    let mut tmp = File::create(&tmp_path)?;
+    tmp.write_all(&content)?;
+    tmp.sync_all()?;
+    fs::rename(&tmp_path, path)?;
+
+    // The rename itself is on the disk once the directory is synced
+    let dir = Path::new(path).parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    File::open(dir)?.sync_all()?;
+    observe_db_save(start);
+    Ok(())
+}
+
+// Back up a snapshot of a former schema version before it is migrated and written again
+fn back_up_former_schema(path: &str, key: Option<&DbKey>) -> Result<(), Box<dyn Error>> {
+    let version = schema_version(&read_snapshot(path, key)?.0)?;
+    if version >= SCHEMA_VERSION {
+        return Ok(());
+    }
+
+    // The journal holds changes made with the former schema
+    if fs::metadata(journal_path(path)).is_ok_and(|journal| journal.len() > 0) {
+        return Err(format!("The journal of {} must be compacted by a server of schema version {} \
+                            before the migration", path, version).into());
+    }
+    let backup = back_up_file(path)?;
+    info!("Database of schema version {} backed up to {} before its migration to version {}",
+          version, backup, SCHEMA_VERSION);
+    Ok(())
+}
+
+impl RonUserStore {
+    /// Load the store and compact its journal, the file is created with the default accounts if it doesn't exist
+    /// The files are encrypted with the key given in the config, if any, and migrated to the current schema
+    /// # Arguments
+    /// * `path` - path of the RON file
+    pub fn open(path: &str) -> Result<RonUserStore, Box<dyn Error>> {
+        let key = load_key(path, config_key_source().as_ref())?;
+        let (users, generation) = if Path::new(path).exists() {
+            back_up_former_schema(path, key.as_ref())?;
+            load(path, key.as_ref())?
+        } else {
+            (Database::default().data, 0)
+        };
+
+        // A journal cut by a crash can't be appended to
+        write_snapshot(path, &users, key.as_ref(), generation)?;
+        let journal = Journal::open(&journal_path(path), generation)?;
+
+        Ok(RonUserStore {
+            users: RwLock::new(users),
+            journal: Mutex::new(journal),
+            path: path.to_string(),
+            key,
         })
     }
 
-    pub fn values() -> Result<Vec<UserAccount>, Box<dyn Error>> {
-        Ok(DB.borrow_data()?.data.values().cloned().collect())
+    fn apply(&self, entry: JournalEntry) -> Result<(), Box<dyn Error>> {
+        let _write = begin_write()?;
+        let mut users = self.users.write().map_err(|_| POISONED_STORE)?;
+        self.commit(&mut users, entry)
+    }
+
+    // Journal a change then apply it, the journal is compacted when it is too long
+    fn commit(&self, users: &mut Users, entry: JournalEntry) -> Result<(), Box<dyn Error>> {
+        let mut journal = self.journal.lock().map_err(|_| POISONED_STORE)?;
+
+        let start = Instant::now();
+        journal.append(&entry, self.key.as_ref())?;
+        observe_db_save(start);
+        entry.apply(users);
+
+        if journal.entries() >= CONFIG.journal_max_entries {
+            self.compact(users, &mut journal)?;
+        }
+        Ok(())
+    }
+
+    // The changes already in the snapshot can be applied again if the journal isn't cleared,
+    // or are recognized by their former generation if they are encrypted
+    fn compact(&self, users: &Users, journal: &mut Journal) -> Result<(), Box<dyn Error>> {
+        let generation = journal.generation() + 1;
+        write_snapshot(&self.path, users, self.key.as_ref(), generation)?;
+        journal.clear(generation)
+    }
+}
+
+// Changes made by a transaction, seen by its task on top of the current accounts
+struct JournaledTransaction<'a> {
+    users: &'a Users,
+    changed: Users,
+    entries: Vec<JournalEntry>,
+}
+
+impl UserTransaction for JournaledTransaction<'_> {
+    fn get(&self, username: &str) -> Result<Option<UserAccount>, Box<dyn Error>> {
+        Ok(self.changed.get(username).or_else(|| self.users.get(username)).cloned())
     }
 
-    pub fn init() {
-        // Do nothing but awake the lazy_static
-        let _ = &DB.borrow_data();
+    fn insert(&mut self, user: &UserAccount) -> Result<(), Box<dyn Error>> {
+        self.changed.insert(user.username().to_string(), user.clone());
+        self.entries.push(JournalEntry::Insert(Box::new(user.clone())));
+        Ok(())
+    }
+}
+
+impl UserStore for RonUserStore {
+    fn get(&self, username: &str) -> Result<Option<UserAccount>, Box<dyn Error>> {
+        Ok(self.users.read().map_err(|_| POISONED_STORE)?.get(username).cloned())
+    }
+
+    fn insert(&self, user: &UserAccount) -> Result<(), Box<dyn Error>> {
+        self.apply(JournalEntry::Insert(Box::new(user.clone())))
+    }
+
+    fn delete(&self, username: &str) -> Result<bool, Box<dyn Error>> {
+        if self.get(username)?.is_none() {
+            return Ok(false);
+        }
+        self.apply(JournalEntry::Delete(username.to_string()))?;
+        Ok(true)
+    }
+
+    fn list(&self) -> Result<Vec<UserAccount>, Box<dyn Error>> {
+        Ok(self.users.read().map_err(|_| POISONED_STORE)?.values().cloned().collect())
+    }
+
+    fn get_by_phone(&self, phone_number: &str) -> Result<Vec<UserAccount>, Box<dyn Error>> {
+        let users = self.users.read().map_err(|_| POISONED_STORE)?;
+        Ok(users.values().filter(|user| user.phone_number() == phone_number).cloned().collect())
+    }
+
+    fn transaction(&self, task: TransactionTask) -> Result<(), Box<dyn Error>> {
+        // The accounts can't change until the changes of the task are applied
+        let _write = begin_write()?;
+        let mut users = self.users.write().map_err(|_| POISONED_STORE)?;
+        let mut tx = JournaledTransaction { users: &users, changed: Users::new(), entries: Vec::new() };
+        task(&mut tx)?;
+        let entries = tx.entries;
+        if entries.is_empty() {
+            return Ok(());
+        }
+        self.commit(&mut users, JournalEntry::Transaction(entries))
+    }
+
+    fn flush(&self) -> Result<(), Box<dyn Error>> {
+        let users = self.users.read().map_err(|_| POISONED_STORE)?;
+        let mut journal = self.journal.lock().map_err(|_| POISONED_STORE)?;
+        self.compact(&users, &mut journal)
+    }
+
+    /// Check that the database is usable and that its files can be loaded
+    fn check(&self) -> Result<(), Box<dyn Error>> {
+        drop(self.users.read().map_err(|_| POISONED_STORE)?);
+        load(&self.path, self.key.as_ref())?;
+        Ok(())
+    }
+
+    /// Write the accounts to a snapshot without journal, encrypted like the database
+    fn backup(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        // The accounts can't change while they are written
+        let users = self.users.read().map_err(|_| POISONED_STORE)?;
+        write_snapshot(path, &users, self.key.as_ref(), 0)
     }
 }
 
 impl Default for Database {
     fn default() -> Self {
-        let mut db = Database {
-            data: HashMap::new(),
-        };
+        let data = default_accounts()
+            .into_iter()
+            .map(|user| (user.username().to_string(), user))
+            .collect();
+        Database { version: SCHEMA_VERSION, data }
+    }
+}
 
-        info!("Creating starting data for database");
+/// Create the accounts of the config, given to a new database
+pub fn default_accounts() -> Vec<UserAccount> {
+    info!("Creating starting data for database");
 
-        // Reads env file
-        let config = match read_env_file() {
-            Ok(config) => config,
-            Err(e) => {
-                error!("An error occurred reading env file: {}", e);
-                panic!("An error occurred reading env file: {}", e)
-            }
-        };
+    // Reads env file
+    let config = &*CONFIG;
 
-        let (default_salt_user, default_hash_pwd_user)
-            = new_hash_password(&config.default_user_password);
-        let (default_salt_hr, default_hash_pwd_hr)
-            = new_hash_password(&config.default_hr_password);
+    let default_hash_pwd_user = new_password_hash(&config.default_user_password);
+    let default_hash_pwd_hr = new_password_hash(&config.default_hr_password);
 
-        let user = UserAccount::new(
-            config.default_user,
-            default_hash_pwd_user,
-            default_salt_user,
-            config.default_user_phone,
-            UserRole::StandardUser,
-        );
+    let user = UserAccount::new(
+        config.default_user.clone(),
+        default_hash_pwd_user,
+        config.default_user_phone.clone(),
+        UserRole::StandardUser,
+    );
 
-        let hr = UserAccount::new(
-            config.default_hr,
-            default_hash_pwd_hr,
-            default_salt_hr,
-            config.default_hr_phone,
-            UserRole::HR,
-        );
+    let hr = UserAccount::new(
+        config.default_hr.clone(),
+        default_hash_pwd_hr,
+        config.default_hr_phone.clone(),
+        UserRole::HR,
+    );
 
-        db.data.insert(user.username().to_string(), user);
-        db.data.insert(hr.username().to_string(), hr);
+    vec![user, hr]
+}
 
-        db
-    }
+/// Read the accounts of a RON file, e.g. to import them in another store
+/// The file is decrypted with the key given in the config, if any
+/// # Arguments
+/// * `path` - path of the RON file
+pub fn load_ron_users(path: &str) -> Result<Vec<UserAccount>, Box<dyn Error>> {
+    let key = load_key(path, config_key_source().as_ref())?;
+    Ok(load(path, key.as_ref())?.0.into_values().collect())
+}
+
+/// Re-encrypt the RON database with a new key, the server must be stopped
+/// The journal is compacted with the old key first, so the database is consistent at every step
+/// # Arguments
+/// * `path` - path of the RON file
+/// * `old` - current key, none if the database isn't encrypted
+/// * `new` - new key, none to decrypt the database
+/// # Returns
+/// * `Result<usize, Box<dyn Error>>` - The number of accounts re-encrypted
+pub fn rotate_key(path: &str, old: Option<&KeySource>, new: Option<&KeySource>) -> Result<usize, Box<dyn Error>> {
+    let old_key = load_key(path, old)?;
+    back_up_former_schema(path, old_key.as_ref())?;
+    let (users, generation) = load(path, old_key.as_ref())?;
+    write_snapshot(path, &users, old_key.as_ref(), generation)?;
+    Journal::open(&journal_path(path), generation)?;
+
+    // A new passphrase is derived with a new salt
+    let new_key = match new {
+        Some(source) => Some(DbKey::load(source, None)?),
+        None => None,
+    };
+    write_snapshot(path, &users, new_key.as_ref(), generation)?;
+    Ok(users.len())
 }
@@ -0,0 +1,220 @@
+/// This file is used to encrypt the RON database at rest with XChaCha20-Poly1305
+/// The key is read from a key file, 32 bytes in hex, or derived from a passphrase with Argon2id
+/// An encrypted snapshot starts with a header holding the salt of the passphrase and the generation
+/// of the snapshot, authenticated with the content. Each line of the journal is encrypted on its own
+/// and authenticated with the header and its line number, so the lines can't be moved to another
+/// journal or another place of the journal
+use crate::env_reader::CONFIG;
+use crate::hex_tools::{from_hex, to_hex};
+use argon2::{Config, ThreadMode, Variant, Version};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use input_validation::secret::Secret;
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+
+const MAGIC: &[u8] = b"RESIGN-ENCRYPTED-DB-1\n";
+const SALT_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 24;
+const GENERATION_LENGTH: usize = 8;
+const KEY_LENGTH: usize = 32;
+// The key derivation can't change once a database is encrypted with it
+const PASSPHRASE_MEMORY: u32 = 65536; // KiB
+const PASSPHRASE_ITERATIONS: u32 = 3;
+const DECRYPTION_FAILED: &str = "The database can't be decrypted, the key is wrong or the file was modified";
+
+/// Where the key of the database comes from
+pub enum KeySource {
+    File(String),
+    Passphrase(Secret<String>),
+}
+
+/// Key of the database, with the salt it was derived with
+pub struct DbKey {
+    key: Secret<[u8; KEY_LENGTH]>,
+    salt: [u8; SALT_LENGTH],
+}
+
+/// Get the key source given in the config
+/// # Returns
+/// * `Option<KeySource>` - The key source, none if the database isn't encrypted
+pub fn config_key_source() -> Option<KeySource> {
+    match (&CONFIG.db_key_path, &CONFIG.db_passphrase) {
+        (Some(path), _) => Some(KeySource::File(path.clone())),
+        (None, Some(passphrase)) => Some(KeySource::Passphrase(passphrase.clone())),
+        (None, None) => None,
+    }
+}
+
+/// Check if the content of a snapshot is encrypted
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// Write a new random key to a key file only readable by its owner
+/// # Arguments
+/// * `path` - path of the key file, which must not exist
+pub fn generate_key_file(path: &str) -> Result<(), Box<dyn Error>> {
+    let key: Secret<[u8; KEY_LENGTH]> = Secret::new(rand::random());
+    let mut file = OpenOptions::new().write(true).create_new(true).mode(0o600).open(path)?;
+    writeln!(file, "{}", to_hex(key.expose()))?;
+    file.sync_all()?;
+    Ok(())
+}
+
+impl DbKey {
+    /// Load the key from its source
+    /// # Arguments
+    /// * `source` - key file or passphrase
+    /// * `encrypted` - existing encrypted snapshot, whose salt derives the passphrase, if any
+    pub fn load(source: &KeySource, encrypted: Option<&[u8]>) -> Result<DbKey, Box<dyn Error>> {
+        let salt: [u8; SALT_LENGTH] = match encrypted {
+            Some(data) => data
+                .get(MAGIC.len()..MAGIC.len() + SALT_LENGTH)
+                .ok_or("The encrypted database is truncated")?
+                .try_into()?,
+            None => rand::random(),
+        };
+
+        let key: Vec<u8> = match source {
+            KeySource::File(path) => {
+                let content = Secret::new(fs::read_to_string(path)?);
+                from_hex(content.expose().trim()).ok_or("Invalid database key, it must be hexadecimal")?
+            }
+            KeySource::Passphrase(passphrase) => {
+                let config = Config {
+                    variant: Variant::Argon2id,
+                    version: Version::Version13,
+                    mem_cost: PASSPHRASE_MEMORY,
+                    time_cost: PASSPHRASE_ITERATIONS,
+                    lanes: 1,
+                    thread_mode: ThreadMode::Sequential,
+                    hash_length: KEY_LENGTH as u32,
+                    ..Config::default()
+                };
+                argon2::hash_raw(passphrase.expose().as_bytes(), &salt, &config)?
+            }
+        };
+        let key = Secret::new(key);
+        let key: [u8; KEY_LENGTH] = key
+            .expose()
+            .as_slice()
+            .try_into()
+            .map_err(|_| format!("Invalid database key, it must be {} bytes", KEY_LENGTH))?;
+        Ok(DbKey { key: Secret::new(key), salt })
+    }
+
+    fn cipher(&self) -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new(Key::from_slice(self.key.expose()))
+    }
+
+    // The generation is incremented at each compaction of the journal
+    fn header(&self, generation: u64) -> Vec<u8> {
+        [MAGIC, &self.salt, &generation.to_be_bytes()].concat()
+    }
+
+    // Nonce followed by the ciphertext, a random nonce is safe with XChaCha20
+    fn seal(&self, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        let nonce: [u8; NONCE_LENGTH] = rand::random();
+        let ciphertext = self
+            .cipher()
+            .encrypt(XNonce::from_slice(&nonce), Payload { msg: plaintext, aad })
+            .map_err(|_| "The database can't be encrypted")?;
+        Ok([&nonce[..], &ciphertext].concat())
+    }
+
+    fn open(&self, sealed: &[u8], aad: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        if sealed.len() < NONCE_LENGTH {
+            return Err(DECRYPTION_FAILED.into());
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LENGTH);
+        Ok(self
+            .cipher()
+            .decrypt(XNonce::from_slice(nonce), Payload { msg: ciphertext, aad })
+            .map_err(|_| DECRYPTION_FAILED)?)
+    }
+
+    /// Encrypt a snapshot
+    /// # Arguments
+    /// * `plaintext` - snapshot in RON
+    /// * `generation` - generation of the snapshot
+    pub fn encrypt_snapshot(&self, plaintext: &[u8], generation: u64) -> Result<Vec<u8>, Box<dyn Error>> {
+        let header = self.header(generation);
+        let sealed = self.seal(plaintext, &header)?;
+        Ok([header, sealed].concat())
+    }
+
+    /// Decrypt a snapshot, it must have been encrypted with this key
+    /// # Returns
+    /// * `Result<(Vec<u8>, u64), Box<dyn Error>>` - The snapshot in RON and its generation
+    pub fn decrypt_snapshot(&self, data: &[u8]) -> Result<(Vec<u8>, u64), Box<dyn Error>> {
+        let prefix = [MAGIC, &self.salt].concat();
+        let generation = data
+            .strip_prefix(prefix.as_slice())
+            .and_then(|rest| rest.get(..GENERATION_LENGTH))
+            .ok_or(DECRYPTION_FAILED)?;
+        let generation = u64::from_be_bytes(generation.try_into()?);
+        let header = self.header(generation);
+        Ok((self.open(&data[header.len()..], &header)?, generation))
+    }
+
+    // The line is bound to the snapshot whose changes it follows and to its place in the journal
+    fn line_aad(&self, generation: u64, line_number: u64) -> Vec<u8> {
+        [self.header(generation), line_number.to_be_bytes().to_vec()].concat()
+    }
+
+    /// Encrypt a line of the journal
+    /// # Arguments
+    /// * `plaintext` - change in JSON
+    /// * `generation` - generation of the snapshot the journal follows
+    /// * `line_number` - number of the line in the journal, from 0
+    /// # Returns
+    /// * `Result<String, Box<dyn Error>>` - The encrypted line in hex
+    pub fn encrypt_line(&self, plaintext: &[u8], generation: u64, line_number: u64) -> Result<String, Box<dyn Error>> {
+        Ok(to_hex(&self.seal(plaintext, &self.line_aad(generation, line_number))?))
+    }
+
+    /// Decrypt a line of the journal, it must have been encrypted for this generation and line number
+    pub fn decrypt_line(&self, line: &str, generation: u64, line_number: u64) -> Result<Vec<u8>, Box<dyn Error>> {
+        let sealed = from_hex(line).ok_or(DECRYPTION_FAILED)?;
+        self.open(&sealed, &self.line_aad(generation, line_number))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encryption() {
+        let key = DbKey::load(&KeySource::Passphrase(Secret::new("correct horse".to_string())), None).unwrap();
+        let snapshot = key.encrypt_snapshot(b"(data: {})", 7).unwrap();
+        assert!(is_encrypted(&snapshot));
+        assert_eq!(key.decrypt_snapshot(&snapshot).unwrap(), (b"(data: {})".to_vec(), 7));
+
+        // The salt of the snapshot derives the same key
+        let same = DbKey::load(&KeySource::Passphrase(Secret::new("correct horse".to_string())),
+                               Some(&snapshot)).unwrap();
+        assert_eq!(same.decrypt_snapshot(&snapshot).unwrap(), (b"(data: {})".to_vec(), 7));
+        let wrong = DbKey::load(&KeySource::Passphrase(Secret::new("battery staple".to_string())),
+                                Some(&snapshot)).unwrap();
+        assert!(wrong.decrypt_snapshot(&snapshot).is_err());
+
+        let mut modified = snapshot.clone();
+        *modified.last_mut().unwrap() ^= 1;
+        assert!(key.decrypt_snapshot(&modified).is_err());
+
+        // The generation is authenticated
+        let mut modified = snapshot.clone();
+        modified[MAGIC.len() + SALT_LENGTH + GENERATION_LENGTH - 1] ^= 1;
+        assert!(key.decrypt_snapshot(&modified).is_err());
+
+        let line = key.encrypt_line(b"{\"Delete\":\"alice\"}", 7, 3).unwrap();
+        assert_eq!(key.decrypt_line(&line, 7, 3).unwrap(), b"{\"Delete\":\"alice\"}");
+        assert!(wrong.decrypt_line(&line, 7, 3).is_err());
+        assert!(key.decrypt_line(&line, 7, 2).is_err());
+        assert!(key.decrypt_line(&line, 6, 3).is_err());
+    }
+}
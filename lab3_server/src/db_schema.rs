@@ -0,0 +1,234 @@
+/// This file is used to version the schema of the RON database
+/// The snapshot records the version of its schema, the files written before the versions have version 0.
+/// A snapshot of a former version is migrated step by step to the current version when it is loaded,
+/// and a snapshot written by a newer server is refused
+use crate::time_tools::now_timestamp;
+use log::info;
+use rustbreak::{deser::Ron, DeSerializer};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+
+/// Version of the schema written by this server
+pub const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
+
+/// Step bringing a snapshot from a version to the next one
+/// A step reads the snapshot with the types of its version and writes it with the types of the next one,
+/// so it must use copies of the former types, e.g. of `UserAccount`, once the current ones change
+type Migration = fn(&[u8]) -> Result<Vec<u8>, Box<dyn Error>>;
+
+// Each migration brings the snapshot from the version of its index to the next one
+const MIGRATIONS: &[Migration] = &[
+    // 1: versioned snapshot, without the salts stored by the former versions next to the encoded hashes
+    versioned_snapshot,
+];
+
+#[derive(Serialize, Deserialize)]
+struct SchemaVersion {
+    #[serde(default)]
+    version: u32,
+}
+
+/// Read the schema version of a snapshot
+/// # Arguments
+/// * `content` - snapshot in RON
+pub fn schema_version(content: &[u8]) -> Result<u32, Box<dyn Error>> {
+    let version: SchemaVersion = Ron.deserialize(content)?;
+    Ok(version.version)
+}
+
+/// Migrate a snapshot to the current schema version
+/// # Arguments
+/// * `content` - snapshot in RON
+/// # Returns
+/// * `Result<Vec<u8>, Box<dyn Error>>` - The snapshot with the current schema
+pub fn migrate(content: Vec<u8>) -> Result<Vec<u8>, Box<dyn Error>> {
+    let version = schema_version(&content)?;
+    if version > SCHEMA_VERSION {
+        return Err(format!("The database has schema version {}, this server supports up to {}",
+                           version, SCHEMA_VERSION).into());
+    }
+
+    let mut content = content;
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        content = migration(&content)
+            .map_err(|e| format!("The migration of the database to schema version {} failed: {}", i + 1, e))?;
+        info!("Database migrated to schema version {}", i + 1);
+    }
+    Ok(content)
+}
+
+// 0 -> 1, the salts are dropped from the accounts, the other fields are copied as they are
+fn versioned_snapshot(content: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    // The fields nested in the accounts are the same in both versions
+    #[derive(Serialize, Deserialize)]
+    enum Role {
+        StandardUser,
+        HR,
+    }
+    #[derive(Serialize, Deserialize)]
+    struct TwoFactor {
+        totp_secret: String,
+        recovery_codes: Vec<String>,
+        #[serde(default)]
+        last_totp_step: u64,
+    }
+    #[derive(Serialize, Deserialize)]
+    struct PendingPhone {
+        phone_number: String,
+        pin_hash: String,
+        expires_at: u64,
+        attempts_left: u8,
+    }
+    // The hashes of the history are written back in the form they were read
+    #[derive(Serialize, Deserialize)]
+    #[serde(untagged)]
+    enum PasswordHash {
+        Peppered { hash: String, pepper_version: u32 },
+        Plain(String),
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct V0Account {
+        username: String,
+        hash_password: String,
+        #[serde(default)]
+        pepper_version: u32,
+        // Already part of the encoded hash
+        #[serde(default)]
+        salt: String,
+        phone_number: String,
+        role: Role,
+        #[serde(default)]
+        two_factor: Option<TwoFactor>,
+        #[serde(default)]
+        phone_verified: bool,
+        #[serde(default)]
+        pending_phone: Option<PendingPhone>,
+        #[serde(default)]
+        password_history: Vec<PasswordHash>,
+        #[serde(default = "now_timestamp")]
+        password_changed_at: u64,
+    }
+    #[derive(Serialize, Deserialize)]
+    struct V1Account {
+        username: String,
+        hash_password: String,
+        pepper_version: u32,
+        phone_number: String,
+        role: Role,
+        two_factor: Option<TwoFactor>,
+        phone_verified: bool,
+        pending_phone: Option<PendingPhone>,
+        password_history: Vec<PasswordHash>,
+        password_changed_at: u64,
+    }
+    #[derive(Serialize, Deserialize)]
+    struct V0Snapshot {
+        data: HashMap<String, V0Account>,
+    }
+    #[derive(Serialize, Deserialize)]
+    struct V1Snapshot {
+        version: u32,
+        data: HashMap<String, V1Account>,
+    }
+
+    let snapshot: V0Snapshot = Ron.deserialize(content)?;
+    let data = snapshot
+        .data
+        .into_iter()
+        .map(|(key, account)| {
+            let V0Account {
+                username,
+                hash_password,
+                pepper_version,
+                salt: _,
+                phone_number,
+                role,
+                two_factor,
+                phone_verified,
+                pending_phone,
+                password_history,
+                password_changed_at,
+            } = account;
+            let account = V1Account {
+                username,
+                hash_password,
+                pepper_version,
+                phone_number,
+                role,
+                two_factor,
+                phone_verified,
+                pending_phone,
+                password_history,
+                password_changed_at,
+            };
+            (key, account)
+        })
+        .collect();
+    Ok(Ron.serialize(&V1Snapshot { version: 1, data })?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::user_store::Users;
+
+    const UNVERSIONED: &str = r#"(
+    data: {
+        "alice": (
+            username: "alice",
+            hash_password: "hash",
+            salt: "legacy salt",
+            phone_number: "0791112233",
+            role: StandardUser,
+        ),
+        "bob": (
+            username: "bob",
+            hash_password: "hash",
+            pepper_version: 1,
+            salt: "legacy salt",
+            phone_number: "0794445566",
+            role: HR,
+            two_factor: Some((
+                totp_secret: "secret",
+                recovery_codes: ["code"],
+            )),
+            phone_verified: true,
+            password_history: ["plain hash", (hash: "peppered hash", pepper_version: 1)],
+            password_changed_at: 1700000000,
+        ),
+    },
+)"#;
+
+    #[test]
+    fn test_migrate() {
+        assert_eq!(schema_version(UNVERSIONED.as_bytes()).unwrap(), 0);
+        let migrated = migrate(UNVERSIONED.as_bytes().to_vec()).unwrap();
+        assert_eq!(schema_version(&migrated).unwrap(), SCHEMA_VERSION);
+        let text = String::from_utf8(migrated.clone()).unwrap();
+        assert!(text.contains("alice") && !text.contains("legacy salt"));
+
+        // Every other field is kept and read by the current accounts
+        #[derive(Serialize, Deserialize)]
+        struct Snapshot {
+            data: Users,
+        }
+        let snapshot: Snapshot = Ron.deserialize(migrated.as_slice()).unwrap();
+        let bob = &snapshot.data["bob"];
+        assert_eq!(bob.password_hash().pepper_version, 1);
+        assert!(bob.two_factor().is_some() && bob.phone_verified());
+        assert_eq!(bob.password_history().len(), 2);
+        assert_eq!(bob.password_history()[0].hash, "plain hash");
+        assert_eq!(bob.password_history()[1].pepper_version, 1);
+        assert_eq!(bob.password_changed_at(), 1700000000);
+
+        // Already migrated
+        assert_eq!(migrate(migrated.clone()).unwrap(), migrated);
+
+        // Written by a newer server
+        let newer = text.replacen(&format!("version: {}", SCHEMA_VERSION),
+                                  &format!("version: {}", SCHEMA_VERSION + 1), 1);
+        assert!(migrate(newer.into_bytes()).is_err());
+    }
+}
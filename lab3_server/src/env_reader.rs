@@ -1,6 +1,16 @@
 extern crate envfile;
 
 use envfile::EnvFile;
+use input_validation::password::RECOMMENDED_PASSWORD_SCORE;
+use crate::audit_sink::{parse_audit_sinks, AuditSinkKind, SyslogAddress};
+use crate::metrics::parse_metrics_address;
+use crate::user_store::UserStoreKind;
+use crate::logging::{parse_module_levels, LogOutput, LogRotation};
+use input_validation::secret::Secret;
+use lazy_static::lazy_static;
+use log::error;
+use simplelog::LevelFilter;
+use std::net::SocketAddr;
 use std::path::Path;
 use std::error::Error;
 
@@ -14,32 +24,177 @@ use std::error::Error;
 //DEFAULT_HR=
 //DEFAULT_HR_PASSWORD=
 //DEFAULT_HR_PHONE=
+// These values are optional:
+//SMS_OUTBOX_PATH=
+//SESSION_TOKEN_LIFETIME=
+//PASSWORD_HISTORY_SIZE=
+//PASSWORD_MAX_AGE=
+//PASSWORD_EXPIRY_WARNING=
+//PASSWORD_BLOCKLIST_PATH=
+//PASSWORD_MIN_SCORE=
+//ARGON2_MEMORY=
+//ARGON2_ITERATIONS=
+//ARGON2_PARALLELISM=
+//ARGON2_CALIBRATION_MS=
+//PEPPER_PATH=
+//AUDIT_LOG_PATH=
+//AUDIT_SINKS=
+//AUDIT_CEF_PATH=
+//AUDIT_SYSLOG_ADDRESS=
+//USER_STORE=
+//SQLITE_PATH=
+//JOURNAL_MAX_ENTRIES=
+//DB_KEY_PATH=
+//DB_PASSPHRASE=
+//BACKUP_DIR=
+//BACKUP_INTERVAL=
+//BACKUP_RETENTION=
+//METRICS_ADDRESS=
+//SHUTDOWN_TIMEOUT=
+//LOG_OUTPUT=
+//LOG_LEVEL=
+//LOG_MODULE_LEVELS=
+//LOG_PATH=
+//LOG_ROTATION=
+//LOG_MAX_SIZE=
+//LOG_MAX_FILES=
+
+const DEFAULT_SMS_OUTBOX_PATH: &str = "sms_outbox.log";
+const DEFAULT_SESSION_TOKEN_LIFETIME: u64 = 8 * 60 * 60; // seconds
+const DEFAULT_PASSWORD_HISTORY_SIZE: usize = 5;
+const DEFAULT_PASSWORD_MAX_AGE: u64 = 90; // days, 0 to disable
+const DEFAULT_PASSWORD_EXPIRY_WARNING: u64 = 14; // days
+const DEFAULT_PASSWORD_BLOCKLIST_PATH: &str = "password_blocklist.txt";
+const DEFAULT_PASSWORD_MIN_SCORE: u8 = RECOMMENDED_PASSWORD_SCORE;
+const DEFAULT_ARGON2_MEMORY: u32 = 19 * 1024; // KiB
+const DEFAULT_ARGON2_ITERATIONS: u32 = 2;
+const DEFAULT_ARGON2_PARALLELISM: u32 = 1;
+const DEFAULT_ARGON2_CALIBRATION_MS: u64 = 0; // 0 to disable the calibration
+const DEFAULT_AUDIT_LOG_PATH: &str = "audit.jsonl";
+const DEFAULT_AUDIT_CEF_PATH: &str = "audit.cef";
+const DEFAULT_AUDIT_SYSLOG_ADDRESS: &str = "udp://127.0.0.1:514";
+const DEFAULT_USER_STORE: UserStoreKind = UserStoreKind::Ron;
+const DEFAULT_SQLITE_PATH: &str = "db.sqlite";
+const DEFAULT_JOURNAL_MAX_ENTRIES: usize = 1000;
+const DEFAULT_BACKUP_DIR: &str = "backups";
+const DEFAULT_BACKUP_INTERVAL: u64 = 24 * 60 * 60; // seconds, 0 to disable
+const DEFAULT_BACKUP_RETENTION: usize = 7;
+const DEFAULT_SHUTDOWN_TIMEOUT: u64 = 30; // seconds
+const DEFAULT_LOG_OUTPUT: LogOutput = LogOutput::Stderr;
+const DEFAULT_LOG_LEVEL: LevelFilter = LevelFilter::Warn;
+const DEFAULT_LOG_PATH: &str = "server.log";
+const DEFAULT_LOG_ROTATION: LogRotation = LogRotation::Size;
+const DEFAULT_LOG_MAX_SIZE: u64 = 10 * 1024; // KiB
+const DEFAULT_LOG_MAX_FILES: usize = 5;
+
+// The tests use their own configuration, with cheap hashes and their files in the target directory
+#[cfg(not(test))]
+const ENV_FILE: &str = ".env";
+#[cfg(test)]
+const ENV_FILE: &str = "test.env";
+
+lazy_static! {
+    // The server can't work without its configuration
+    pub static ref CONFIG: Config = match read_env_file() {
+        Ok(config) => config,
+        Err(e) => {
+            error!("An error occurred reading env file: {}", e);
+            panic!("An error occurred reading env file: {}", e)
+        }
+    };
+}
 
 pub struct Config {
     pub server_ip: String,
     pub key_path: String,
     pub certificate_path: String,
     pub default_user: String,
-    pub default_user_password: String,
+    pub default_user_password: Secret<String>,
     pub default_user_phone: String,
     pub default_hr: String,
-    pub default_hr_password: String,
+    pub default_hr_password: Secret<String>,
     pub default_hr_phone: String,
+    pub sms_outbox_path: String,
+    pub session_token_lifetime: u64,
+    pub password_history_size: usize,
+    pub password_max_age: u64,
+    pub password_expiry_warning: u64,
+    pub password_blocklist_path: String,
+    pub password_min_score: u8,
+    pub argon2_memory: u32,
+    pub argon2_iterations: u32,
+    pub argon2_parallelism: u32,
+    pub argon2_calibration_ms: u64,
+    pub pepper_path: Option<String>,
+    pub audit_log_path: String,
+    pub audit_sinks: Vec<AuditSinkKind>,
+    pub audit_cef_path: String,
+    pub audit_syslog_address: SyslogAddress,
+    pub user_store: UserStoreKind,
+    pub sqlite_path: String,
+    pub journal_max_entries: usize,
+    pub db_key_path: Option<String>,
+    pub db_passphrase: Option<Secret<String>>,
+    pub backup_dir: String,
+    pub backup_interval: u64,
+    pub backup_retention: usize,
+    pub metrics_address: Option<SocketAddr>,
+    pub shutdown_timeout: u64,
+    pub log_output: LogOutput,
+    pub log_level: LevelFilter,
+    pub log_module_levels: Vec<(String, LevelFilter)>,
+    pub log_path: String,
+    pub log_rotation: LogRotation,
+    pub log_max_size: u64,
+    pub log_max_files: usize,
 }
 
 pub fn read_env_file() -> Result<Config, Box<dyn Error>> {
-    let envfile = EnvFile::new(&Path::new(".env"))?;
+    let envfile = EnvFile::new(&Path::new(ENV_FILE))?;
 
     let mut config = Config {
         server_ip: "".to_string(),
         key_path: "".to_string(),
         certificate_path: "".to_string(),
         default_user: "".to_string(),
-        default_user_password: "".to_string(),
+        default_user_password: Secret::default(),
         default_user_phone: "".to_string(),
         default_hr: "".to_string(),
-        default_hr_password: "".to_string(),
-        default_hr_phone: "".to_string()
+        default_hr_password: Secret::default(),
+        default_hr_phone: "".to_string(),
+        sms_outbox_path: DEFAULT_SMS_OUTBOX_PATH.to_string(),
+        session_token_lifetime: DEFAULT_SESSION_TOKEN_LIFETIME,
+        password_history_size: DEFAULT_PASSWORD_HISTORY_SIZE,
+        password_max_age: DEFAULT_PASSWORD_MAX_AGE,
+        password_expiry_warning: DEFAULT_PASSWORD_EXPIRY_WARNING,
+        password_blocklist_path: DEFAULT_PASSWORD_BLOCKLIST_PATH.to_string(),
+        password_min_score: DEFAULT_PASSWORD_MIN_SCORE,
+        argon2_memory: DEFAULT_ARGON2_MEMORY,
+        argon2_iterations: DEFAULT_ARGON2_ITERATIONS,
+        argon2_parallelism: DEFAULT_ARGON2_PARALLELISM,
+        argon2_calibration_ms: DEFAULT_ARGON2_CALIBRATION_MS,
+        pepper_path: None,
+        audit_log_path: DEFAULT_AUDIT_LOG_PATH.to_string(),
+        audit_sinks: Vec::new(),
+        audit_cef_path: DEFAULT_AUDIT_CEF_PATH.to_string(),
+        audit_syslog_address: DEFAULT_AUDIT_SYSLOG_ADDRESS.parse()?,
+        user_store: DEFAULT_USER_STORE,
+        sqlite_path: DEFAULT_SQLITE_PATH.to_string(),
+        journal_max_entries: DEFAULT_JOURNAL_MAX_ENTRIES,
+        db_key_path: None,
+        db_passphrase: None,
+        backup_dir: DEFAULT_BACKUP_DIR.to_string(),
+        backup_interval: DEFAULT_BACKUP_INTERVAL,
+        backup_retention: DEFAULT_BACKUP_RETENTION,
+        metrics_address: None,
+        shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+        log_output: DEFAULT_LOG_OUTPUT,
+        log_level: DEFAULT_LOG_LEVEL,
+        log_module_levels: Vec::new(),
+        log_path: DEFAULT_LOG_PATH.to_string(),
+        log_rotation: DEFAULT_LOG_ROTATION,
+        log_max_size: DEFAULT_LOG_MAX_SIZE,
+        log_max_files: DEFAULT_LOG_MAX_FILES,
     };
 
     for (key, value) in envfile.store {
@@ -48,11 +203,44 @@ pub fn read_env_file() -> Result<Config, Box<dyn Error>> {
             "KEY_PATH" => config.key_path = format!("{}", value),
             "CERT_PATH" => config.certificate_path = format!("{}", value),
             "DEFAULT_USER" => config.default_user = format!("{}", value),
-            "DEFAULT_USER_PASSWORD" => config.default_user_password = format!("{}", value),
+            "DEFAULT_USER_PASSWORD" => config.default_user_password = Secret::new(value),
             "DEFAULT_USER_PHONE" => config.default_user_phone = format!("{}", value),
             "DEFAULT_HR" => config.default_hr = format!("{}", value),
-            "DEFAULT_HR_PASSWORD" => config.default_hr_password = format!("{}", value),
+            "DEFAULT_HR_PASSWORD" => config.default_hr_password = Secret::new(value),
             "DEFAULT_HR_PHONE" => config.default_hr_phone = format!("{}", value),
+            "SMS_OUTBOX_PATH" => config.sms_outbox_path = value,
+            "SESSION_TOKEN_LIFETIME" => config.session_token_lifetime = value.parse()?,
+            "PASSWORD_HISTORY_SIZE" => config.password_history_size = value.parse()?,
+            "PASSWORD_MAX_AGE" => config.password_max_age = value.parse()?,
+            "PASSWORD_EXPIRY_WARNING" => config.password_expiry_warning = value.parse()?,
+            "PASSWORD_BLOCKLIST_PATH" => config.password_blocklist_path = value,
+            "PASSWORD_MIN_SCORE" => config.password_min_score = value.parse()?,
+            "ARGON2_MEMORY" => config.argon2_memory = value.parse()?,
+            "ARGON2_ITERATIONS" => config.argon2_iterations = value.parse()?,
+            "ARGON2_PARALLELISM" => config.argon2_parallelism = value.parse()?,
+            "ARGON2_CALIBRATION_MS" => config.argon2_calibration_ms = value.parse()?,
+            "PEPPER_PATH" => config.pepper_path = Some(value),
+            "AUDIT_LOG_PATH" => config.audit_log_path = value,
+            "AUDIT_SINKS" => config.audit_sinks = parse_audit_sinks(&value)?,
+            "AUDIT_CEF_PATH" => config.audit_cef_path = value,
+            "AUDIT_SYSLOG_ADDRESS" => config.audit_syslog_address = value.parse()?,
+            "USER_STORE" => config.user_store = value.parse()?,
+            "SQLITE_PATH" => config.sqlite_path = value,
+            "JOURNAL_MAX_ENTRIES" => config.journal_max_entries = value.parse()?,
+            "DB_KEY_PATH" => config.db_key_path = Some(value),
+            "DB_PASSPHRASE" => config.db_passphrase = Some(Secret::new(value)),
+            "BACKUP_DIR" => config.backup_dir = value,
+            "BACKUP_INTERVAL" => config.backup_interval = value.parse()?,
+            "BACKUP_RETENTION" => config.backup_retention = value.parse()?,
+            "METRICS_ADDRESS" => config.metrics_address = Some(parse_metrics_address(&value)?),
+            "SHUTDOWN_TIMEOUT" => config.shutdown_timeout = value.parse()?,
+            "LOG_OUTPUT" => config.log_output = value.parse()?,
+            "LOG_LEVEL" => config.log_level = value.parse()?,
+            "LOG_MODULE_LEVELS" => config.log_module_levels = parse_module_levels(&value)?,
+            "LOG_PATH" => config.log_path = value,
+            "LOG_ROTATION" => config.log_rotation = value.parse()?,
+            "LOG_MAX_SIZE" => config.log_max_size = value.parse()?,
+            "LOG_MAX_FILES" => config.log_max_files = value.parse()?,
             _ => {}
         }
     }
@@ -61,12 +249,25 @@ pub fn read_env_file() -> Result<Config, Box<dyn Error>> {
         || config.key_path == ""
         || config.certificate_path == ""
         || config.default_user == ""
-        || config.default_user_password == ""
+        || config.default_user_password.expose().is_empty()
         || config.default_user_phone == ""
         || config.default_hr == ""
-        || config.default_hr_password == ""
+        || config.default_hr_password.expose().is_empty()
         || config.default_hr_phone == "" {
         Err("Invalid env file".into())
+    } else if config.argon2_iterations == 0
+        || config.argon2_parallelism == 0
+        || config.argon2_memory < 8 * config.argon2_parallelism {
+        Err("Invalid Argon2 parameters".into())
+    } else if config.log_max_size == 0 {
+        Err("Invalid log parameters".into())
+    } else if config.db_key_path.is_some() && config.db_passphrase.is_some() {
+        Err("Invalid database encryption parameters, DB_KEY_PATH and DB_PASSPHRASE can't both be set".into())
+    } else if (config.db_key_path.is_some() || config.db_passphrase.is_some())
+        && config.user_store != UserStoreKind::Ron {
+        Err("Invalid database encryption parameters, only the RON store can be encrypted".into())
+    } else if config.backup_retention == 0 {
+        Err("Invalid backup parameters, BACKUP_RETENTION must keep at least one backup".into())
     } else {
         Ok(config)
     }
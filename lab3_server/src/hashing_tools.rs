@@ -1,5 +1,146 @@
 use rand::RngCore;
-use argon2::{self, Config};
+use argon2::{self, Config, ThreadMode, Variant, Version};
+use crate::env_reader::CONFIG;
+use crate::metrics::observe_argon2;
+use crate::pepper::{current_pepper, pepper, NO_PEPPER_VERSION};
+use input_validation::secret::Secret;
+use lazy_static::lazy_static;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+// Upper bound of the iterations tried by the calibration
+const CALIBRATION_MAX_ITERATIONS: u32 = 64;
+const CALIBRATION_PASSWORD: &str = "calibration password";
+
+lazy_static! {
+    // Parameters used for every new hash, calibrated once at startup if required
+    static ref ARGON2_PARAMS: Argon2Params = {
+        let params = Argon2Params {
+            memory: CONFIG.argon2_memory,
+            iterations: CONFIG.argon2_iterations,
+            parallelism: CONFIG.argon2_parallelism,
+        };
+        let params = match CONFIG.argon2_calibration_ms {
+            0 => params,
+            target => calibrate_argon2(params, Duration::from_millis(target)),
+        };
+        info!("Argon2id parameters: m={}, t={}, p={}",
+            params.memory, params.iterations, params.parallelism);
+        params
+    };
+
+    // Hash of a random password verified for the unknown users to spend the same time as for the others
+    static ref DUMMY_HASH: PasswordHash = {
+        let mut password = [0u8; 16];
+        generate_random_16_bytes(&mut password);
+        new_password_hash(&Secret::new(String::from_utf8_lossy(&password).into_owned()))
+    };
+}
+
+/// Hash of a password with the version of the pepper mixed into it
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(from = "StoredPasswordHash")]
+pub struct PasswordHash {
+    pub hash: String,
+    pub pepper_version: u32,
+}
+
+// The hashes stored before the peppers are plain encoded hashes
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredPasswordHash {
+    Peppered { hash: String, pepper_version: u32 },
+    Plain(String),
+}
+
+impl From<StoredPasswordHash> for PasswordHash {
+    fn from(stored: StoredPasswordHash) -> Self {
+        match stored {
+            StoredPasswordHash::Peppered { hash, pepper_version } => PasswordHash { hash, pepper_version },
+            StoredPasswordHash::Plain(hash) => PasswordHash { hash, pepper_version: NO_PEPPER_VERSION },
+        }
+    }
+}
+
+/// Cost parameters of Argon2id
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Argon2Params {
+    // Memory in KiB
+    pub memory: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Argon2Params {
+    fn config(&self) -> Config<'static> {
+        Config {
+            variant: Variant::Argon2id,
+            version: Version::Version13,
+            mem_cost: self.memory,
+            time_cost: self.iterations,
+            lanes: self.parallelism,
+            thread_mode: ThreadMode::from_threads(self.parallelism),
+            ..Config::default()
+        }
+    }
+
+    /// Read the variant and the parameters of an encoded hash
+    /// # Arguments
+    /// * `encoded` - encoded hash, e.g. $argon2id$v=19$m=19456,t=2,p=1$salt$hash
+    /// # Returns
+    /// * `Option<Argon2Params>` - The parameters if the hash is an Argon2id hash
+    fn from_encoded(encoded: &str) -> Option<Argon2Params> {
+        let mut parts = encoded.split('$');
+        if parts.nth(1)? != "argon2id" {
+            return None;
+        }
+        let mut params = Argon2Params { memory: 0, iterations: 0, parallelism: 0 };
+        for param in parts.nth(1)?.split(',') {
+            let (key, value) = param.split_once('=')?;
+            let value = value.parse().ok()?;
+            match key {
+                "m" => params.memory = value,
+                "t" => params.iterations = value,
+                "p" => params.parallelism = value,
+                _ => return None,
+            }
+        }
+        Some(params)
+    }
+}
+
+/// Find the number of iterations needed to reach a target hashing time
+/// The memory and the parallelism are kept
+/// # Arguments
+/// * `params` - parameters to start from
+/// * `target` - hashing time to reach
+/// # Returns
+/// * `Argon2Params` - The calibrated parameters
+pub fn calibrate_argon2(params: Argon2Params, target: Duration) -> Argon2Params {
+    let salt = [0u8; 16];
+    let mut params = Argon2Params { iterations: 1, ..params };
+    loop {
+        let start = Instant::now();
+        argon2::hash_raw(CALIBRATION_PASSWORD.as_bytes(), &salt, &params.config()).unwrap();
+        let elapsed = start.elapsed();
+        if elapsed >= target {
+            info!("Argon2id calibration: {} iterations take {:?}", params.iterations, elapsed);
+            return params;
+        }
+        if params.iterations >= CALIBRATION_MAX_ITERATIONS {
+            warn!("Argon2id calibration stopped at {} iterations ({:?})",
+                params.iterations, elapsed);
+            return params;
+        }
+        params.iterations += 1;
+    }
+}
+
+/// Compute the Argon2id parameters at startup, the calibration may take a while
+pub fn init() {
+    let _ = &*DUMMY_HASH;
+}
 
 /// Generate a random number of 16 bytes / 128 bits
 /// Used to generate salt
@@ -10,25 +151,107 @@ pub fn generate_random_16_bytes(bytes: &mut [u8; 16]) {
     rng.fill_bytes(bytes);
 }
 
-/// Hash a given data (password) with salt using argon2 algorithme
+/// Hash a given data (password) with salt using argon2id algorithme and the configured parameters
 /// We assume that the hash function will always works
 /// # Arguments
 /// * `data` - data to hash (mostly passwords)
 /// * `salt` - salt used to hash the data
+/// * `secret` - pepper mixed into the hash, empty if none
 /// # Returns
 /// * `String` - The hash generated
-pub fn hash_argon2(data: &str, salt: &[u8]) -> String {
-    argon2::hash_encoded(data.as_bytes(), salt, &Config::default()).unwrap()
+pub fn hash_argon2(data: &str, salt: &[u8], secret: &[u8]) -> String {
+    let config = Config { secret, ..ARGON2_PARAMS.config() };
+    let start = Instant::now();
+    let hash = argon2::hash_encoded(data.as_bytes(), salt, &config).unwrap();
+    observe_argon2("hash", start);
+    hash
 }
 
-/// Create a new hash and salt for a given password
+/// Create a new hash with a random salt for a given data, without pepper
+/// The salt is part of the encoded hash
+/// # Arguments
+/// * `password` - data to hash (codes, PINs)
+/// # Returns
+/// * `String` - The encoded hash
+pub fn new_hash_password(password: &str) -> String {
+    let mut salt: [u8; 16] = [0; 16];
+    generate_random_16_bytes(&mut salt);
+    hash_argon2(password, &salt, &[])
+}
+
+/// Create a new hash with a random salt and the current pepper for a given password
 /// # Arguments
 /// * `password` - password to hash
 /// # Returns
-/// * `([u8;16], String)` - A tuple containing the salt and the hash
-pub fn new_hash_password(password: &str) -> ([u8; 16], String) {
+/// * `PasswordHash` - The encoded hash and the version of the pepper
+pub fn new_password_hash(password: &Secret<String>) -> PasswordHash {
     let mut salt: [u8; 16] = [0; 16];
     generate_random_16_bytes(&mut salt);
-    let hash_password = hash_argon2(password, &mut salt);
-    (salt, hash_password)
-}
\ No newline at end of file
+    let (pepper_version, pepper) = current_pepper();
+    PasswordHash {
+        hash: hash_argon2(password.expose(), &salt, pepper),
+        pepper_version,
+    }
+}
+
+/// Verify a given data against an encoded argon2 hash
+/// The salt and the parameters are read from the encoded hash, the hashes are compared in constant time
+/// # Arguments
+/// * `encoded` - encoded hash to verify against
+/// * `data` - data to verify (mostly passwords or codes)
+/// # Returns
+/// * `bool` - True if the data matches the hash, false otherwise
+pub fn verify_hash(encoded: &str, data: &str) -> bool {
+    let start = Instant::now();
+    let valid = argon2::verify_encoded(encoded, data.as_bytes()).unwrap_or(false);
+    observe_argon2("verify", start);
+    valid
+}
+
+/// Verify a password against its hash with the pepper of the hash
+/// # Arguments
+/// * `password_hash` - hash to verify against
+/// * `password` - password to verify
+/// # Returns
+/// * `bool` - True if the password matches the hash, false otherwise
+pub fn verify_password(password_hash: &PasswordHash, password: &Secret<String>) -> bool {
+    match pepper(password_hash.pepper_version) {
+        Some(pepper) => {
+            let start = Instant::now();
+            let valid = argon2::verify_encoded_ext(&password_hash.hash, password.expose().as_bytes(), pepper, &[])
+                .unwrap_or(false);
+            observe_argon2("verify", start);
+            valid
+        }
+        None => {
+            error!("Pepper {} is missing, the password can't be verified", password_hash.pepper_version);
+            false
+        }
+    }
+}
+
+/// Check if an encoded hash has been made with other parameters than the current ones
+/// # Arguments
+/// * `encoded` - encoded hash to check
+/// # Returns
+/// * `bool` - True if the data should be hashed again, false otherwise
+pub fn needs_rehash(encoded: &str) -> bool {
+    Argon2Params::from_encoded(encoded) != Some(*ARGON2_PARAMS)
+}
+
+/// Check if a password hash has been made with other parameters or another pepper than the current ones
+/// # Arguments
+/// * `password_hash` - hash to check
+/// # Returns
+/// * `bool` - True if the password should be hashed again, false otherwise
+pub fn password_needs_rehash(password_hash: &PasswordHash) -> bool {
+    needs_rehash(&password_hash.hash) || password_hash.pepper_version != current_pepper().0
+}
+
+/// Verify a given password against a dummy hash made with the current parameters and pepper
+/// Used when there is no hash to verify against, to take as long as a real verification
+/// # Arguments
+/// * `password` - password to verify
+pub fn verify_dummy_hash(password: &Secret<String>) {
+    verify_password(&DUMMY_HASH, password);
+}
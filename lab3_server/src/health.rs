@@ -0,0 +1,63 @@
+/// This file is used to tell an orchestrator whether the server is alive and ready
+/// The server is alive while its shared state is usable, and ready when the database loads,
+/// the access control policy builds and the TLS identity is valid
+use crate::access_control::check_access_control;
+use crate::audit;
+use crate::build_tls_acceptor;
+use crate::env_reader::CONFIG;
+use crate::user_store::UserStore;
+use log::warn;
+use serde::Serialize;
+use std::error::Error;
+
+/// State of a component of the server
+#[derive(Serialize, Debug)]
+pub struct ComponentHealth {
+    pub component: &'static str,
+    pub healthy: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Result of a health check with the state of each component checked
+#[derive(Serialize, Debug)]
+pub struct Health {
+    pub healthy: bool,
+    pub components: Vec<ComponentHealth>,
+}
+
+fn check(component: &'static str, result: Result<(), Box<dyn Error>>) -> ComponentHealth {
+    match result {
+        Ok(()) => ComponentHealth { component, healthy: true, error: None },
+        Err(e) => {
+            warn!("Health check of {} failed: {}", component, e);
+            ComponentHealth { component, healthy: false, error: Some(e.to_string()) }
+        }
+    }
+}
+
+fn health(components: Vec<ComponentHealth>) -> Health {
+    Health { healthy: components.iter().all(|c| c.healthy), components }
+}
+
+/// Check if the server is alive, a dead server must be restarted
+/// # Returns
+/// * `Health` - The state of the audit log
+pub fn liveness() -> Health {
+    health(vec![
+        check("audit_log", audit::check()),
+    ])
+}
+
+/// Check if the server is ready to handle clients
+/// # Arguments
+/// * `store` - store of the user accounts
+/// # Returns
+/// * `Health` - The state of the database, the access control and the TLS identity
+pub fn readiness(store: &dyn UserStore) -> Health {
+    health(vec![
+        check("database", store.check()),
+        check("access_control", check_access_control()),
+        check("tls_identity", build_tls_acceptor(&CONFIG.certificate_path, &CONFIG.key_path).map(|_| ())),
+    ])
+}
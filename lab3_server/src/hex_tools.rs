@@ -0,0 +1,38 @@
+/// Encode bytes in lowercase hexadecimal
+/// # Arguments
+/// * `bytes` - bytes to encode
+/// # Returns
+/// * `String` - Two hexadecimal digits per byte
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Decode hexadecimal, in lowercase or uppercase
+/// # Arguments
+/// * `hex` - hexadecimal to decode
+/// # Returns
+/// * `Option<Vec<u8>>` - The bytes, None if the hexadecimal is invalid
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(to_hex(&[0x00, 0x1f, 0xab, 0xff]), "001fabff");
+        assert_eq!(from_hex("001fABff"), Some(vec![0x00, 0x1f, 0xab, 0xff]));
+        assert_eq!(from_hex(""), Some(Vec::new()));
+        assert_eq!(from_hex("abc"), None);
+        assert_eq!(from_hex("zz"), None);
+        assert_eq!(from_hex("é1"), None);
+    }
+}
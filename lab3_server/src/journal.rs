@@ -0,0 +1,234 @@
+/// This file is used to journal the changes of the RON store
+/// Each change is appended as a JSON line and synced to the disk before it is applied, so the
+/// snapshot only has to be rewritten when the journal is compacted
+/// When the database is encrypted, each line is encrypted on its own for the generation of the snapshot
+/// and its line number
+use crate::db_crypto::DbKey;
+use crate::user::UserAccount;
+use crate::user_store::Users;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+
+/// A change of the accounts
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum JournalEntry {
+    // Boxed so that the deletions stay small, serialized like the account itself
+    Insert(Box<UserAccount>),
+    Delete(String),
+    // Changes applied all together or not at all
+    Transaction(Vec<JournalEntry>),
+}
+
+impl JournalEntry {
+    /// Apply the change to the accounts, applying it again gives the same accounts
+    pub fn apply(self, users: &mut Users) {
+        match self {
+            JournalEntry::Insert(user) => {
+                users.insert(user.username().to_string(), *user);
+            }
+            JournalEntry::Delete(username) => {
+                users.remove(&username);
+            }
+            JournalEntry::Transaction(entries) => entries.into_iter().for_each(|entry| entry.apply(users)),
+        }
+    }
+}
+
+/// Journal file open for appending
+pub struct Journal {
+    file: File,
+    entries: usize,
+    // Generation of the snapshot whose changes are journaled
+    generation: u64,
+}
+
+impl Journal {
+    /// Open the journal of a snapshot, created if it doesn't exist
+    /// The changes it held must already be in the snapshot, they are removed
+    /// # Arguments
+    /// * `path` - path of the journal
+    /// * `generation` - generation of the snapshot
+    pub fn open(path: &str, generation: u64) -> Result<Journal, Box<dyn Error>> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let mut journal = Journal { file, entries: 0, generation };
+        journal.clear(generation)?;
+        Ok(journal)
+    }
+
+    /// Append a change and wait until it is on the disk
+    /// # Arguments
+    /// * `entry` - change to append
+    /// * `key` - key of the database, if it is encrypted
+    pub fn append(&mut self, entry: &JournalEntry, key: Option<&DbKey>) -> Result<(), Box<dyn Error>> {
+        let line = serde_json::to_vec(entry)?;
+        let mut line = match key {
+            Some(key) => key.encrypt_line(&line, self.generation, self.entries as u64)?.into_bytes(),
+            None => line,
+        };
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        self.file.sync_data()?;
+        self.entries += 1;
+        Ok(())
+    }
+
+    /// Number of changes since the last compaction
+    pub fn entries(&self) -> usize {
+        self.entries
+    }
+
+    /// Generation of the snapshot whose changes are journaled
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Empty the journal once its changes are in the snapshot
+    /// # Arguments
+    /// * `generation` - generation of the snapshot holding the changes
+    pub fn clear(&mut self, generation: u64) -> Result<(), Box<dyn Error>> {
+        self.file.set_len(0)?;
+        self.file.sync_all()?;
+        self.entries = 0;
+        self.generation = generation;
+        Ok(())
+    }
+}
+
+/// Apply the changes of a journal to the accounts of the snapshot
+/// A last line without its end of line was cut by a crash and is ignored, any other invalid line is an error
+/// # Arguments
+/// * `path` - path of the journal
+/// * `users` - accounts of the snapshot
+/// * `key` - key of the database, if it is encrypted
+/// * `generation` - generation of the snapshot
+/// # Returns
+/// * `Result<usize, Box<dyn Error>>` - The number of changes applied
+pub fn replay(path: &str, users: &mut Users, key: Option<&DbKey>, generation: u64) -> Result<usize, Box<dyn Error>> {
+    let content = match fs::read(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e.into()),
+    };
+
+    // The last piece is empty when the last line is complete
+    let mut lines: Vec<&[u8]> = content.split(|b| *b == b'\n').collect();
+    if lines.pop().is_some_and(|last| !last.is_empty()) {
+        warn!("Ignoring the incomplete last change of the journal {}", path);
+    }
+
+    // A crash between the writing of a snapshot and the clearing of its journal leaves the changes
+    // of the former generation, which are already in the snapshot
+    let stale = key.is_some() && generation > 0 && lines.first().is_some_and(|line| {
+        decode(line, key, generation, 0).is_err() && decode(line, key, generation - 1, 0).is_ok()
+    });
+    let generation = if stale { generation - 1 } else { generation };
+
+    let mut entries = Vec::with_capacity(lines.len());
+    for (i, line) in lines.iter().enumerate() {
+        let entry = decode(line, key, generation, i as u64)
+            .map_err(|e| format!("Invalid change {} in the journal {}: {}", i + 1, path, e))?;
+        entries.push(entry);
+    }
+    if stale {
+        info!("The changes of the journal {} are already in the snapshot", path);
+        return Ok(0);
+    }
+
+    let applied = entries.len();
+    entries.into_iter().for_each(|entry| entry.apply(users));
+    Ok(applied)
+}
+
+fn decode(line: &[u8], key: Option<&DbKey>, generation: u64, line_number: u64) -> Result<JournalEntry, Box<dyn Error>> {
+    match key {
+        Some(key) => Ok(serde_json::from_slice(&key.decrypt_line(std::str::from_utf8(line)?, generation, line_number)?)?),
+        None => Ok(serde_json::from_slice(line)?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_crypto::{generate_key_file, KeySource};
+    use crate::hashing_tools::PasswordHash;
+    use crate::user::UserRole;
+    use std::env;
+    use std::fs;
+
+    fn user(username: &str) -> UserAccount {
+        let hash = PasswordHash { hash: "hash".to_string(), pepper_version: 0 };
+        UserAccount::new(username.to_string(), hash, "0791112233".to_string(), UserRole::StandardUser)
+    }
+
+    #[test]
+    fn test_journal_replay() {
+        let path = env::temp_dir().join(format!("journal-test-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let mut journal = Journal::open(path, 0).unwrap();
+        journal.append(&JournalEntry::Insert(Box::new(user("alice"))), None).unwrap();
+        journal.append(&JournalEntry::Transaction(vec![
+            JournalEntry::Insert(Box::new(user("bob"))),
+            JournalEntry::Delete("alice".to_string()),
+        ]), None).unwrap();
+        assert_eq!(journal.entries(), 2);
+
+        let mut users = Users::new();
+        assert_eq!(replay(path, &mut users, None, 0).unwrap(), 2);
+        assert!(users.contains_key("bob") && !users.contains_key("alice"));
+
+        // Last change cut by a crash before its end of line
+        journal.file.write_all(b"{\"Insert\":{").unwrap();
+        let mut users = Users::new();
+        assert_eq!(replay(path, &mut users, None, 0).unwrap(), 2);
+
+        // Invalid change with its end of line, last or followed by valid ones
+        journal.file.write_all(b"\n").unwrap();
+        assert!(replay(path, &mut Users::new(), None, 0).is_err());
+        journal.append(&JournalEntry::Delete("bob".to_string()), None).unwrap();
+        assert!(replay(path, &mut Users::new(), None, 0).is_err());
+
+        journal.clear(0).unwrap();
+        assert_eq!(replay(path, &mut Users::new(), None, 0).unwrap(), 0);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_encrypted_journal_replay() {
+        let path = env::temp_dir().join(format!("journal-test-encrypted-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let key_path = format!("{}.key", path);
+        let _ = fs::remove_file(&key_path);
+        generate_key_file(&key_path).unwrap();
+        let key = DbKey::load(&KeySource::File(key_path.clone()), None).unwrap();
+
+        // Encrypted changes can only be read with their key
+        let mut journal = Journal::open(path, 3).unwrap();
+        journal.append(&JournalEntry::Insert(Box::new(user("carol"))), Some(&key)).unwrap();
+        journal.append(&JournalEntry::Insert(Box::new(user("dave"))), Some(&key)).unwrap();
+        assert!(replay(path, &mut Users::new(), None, 3).is_err());
+        let mut users = Users::new();
+        assert_eq!(replay(path, &mut users, Some(&key), 3).unwrap(), 2);
+        assert!(users.contains_key("carol") && users.contains_key("dave"));
+
+        // Journal of the former generation, left by a crash during a compaction
+        let mut users = Users::new();
+        assert_eq!(replay(path, &mut users, Some(&key), 4).unwrap(), 0);
+        assert!(users.is_empty());
+        assert!(replay(path, &mut Users::new(), Some(&key), 5).is_err());
+
+        // The lines can't be moved
+        let content = fs::read_to_string(path).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        fs::write(path, format!("{}\n{}\n", lines[1], lines[0])).unwrap();
+        assert!(replay(path, &mut Users::new(), Some(&key), 3).is_err());
+        fs::write(path, format!("{}\n", lines[1])).unwrap();
+        assert!(replay(path, &mut Users::new(), Some(&key), 3).is_err());
+
+        fs::remove_file(path).unwrap();
+        fs::remove_file(key_path).unwrap();
+    }
+}
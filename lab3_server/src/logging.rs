@@ -0,0 +1,223 @@
+/// This file is used to configure the server logs from the config
+/// The logs are written to stderr, to a file or both, with a level per module
+/// The log file is appended to and rotated by size or every day, the older files are numbered:
+/// `server.log.1` is the most recent one
+use crate::env_reader::CONFIG;
+use crate::time_tools::now_timestamp;
+use log::{Log, Metadata, Record};
+use simplelog::{ColorChoice, Config, ConfigBuilder, LevelFilter, SharedLogger, TermLogger, TerminalMode,
+                WriteLogger};
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::time::UNIX_EPOCH;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Where the logs are written
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LogOutput {
+    Stderr,
+    File,
+    Both,
+}
+
+/// When the log file is rotated
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LogRotation {
+    // When the file would exceed the maximum size
+    Size,
+    // At the first record of a new day, midnight UTC
+    Daily,
+}
+
+impl FromStr for LogOutput {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "stderr" => Ok(LogOutput::Stderr),
+            "file" => Ok(LogOutput::File),
+            "both" => Ok(LogOutput::Both),
+            _ => Err(format!("Invalid log output: {}", s).into()),
+        }
+    }
+}
+
+impl FromStr for LogRotation {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "size" => Ok(LogRotation::Size),
+            "daily" => Ok(LogRotation::Daily),
+            _ => Err(format!("Invalid log rotation: {}", s).into()),
+        }
+    }
+}
+
+/// Parse the levels of the modules
+/// # Arguments
+/// * `value` - comma-separated list of `<module path>=<level>`, e.g. `lab3_server::action=debug`
+/// # Returns
+/// * `Result<Vec<(String, LevelFilter)>, Box<dyn Error>>` - The module paths and their level
+pub fn parse_module_levels(value: &str) -> Result<Vec<(String, LevelFilter)>, Box<dyn Error>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (module, level) = entry.split_once('=').ok_or("Invalid module log level")?;
+            Ok((module.trim().to_string(), level.trim().parse()?))
+        })
+        .collect()
+}
+
+/// Log file rotated by size or every day
+struct RotatingFile {
+    path: String,
+    file: File,
+    size: u64,
+    day: u64,
+    // A record is never split between two files
+    at_line_start: bool,
+}
+
+impl RotatingFile {
+    /// Open the log file in append mode, the existing records are kept
+    fn open(path: &str) -> io::Result<RotatingFile> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let metadata = file.metadata()?;
+        let modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        Ok(RotatingFile {
+            path: path.to_string(),
+            file,
+            size: metadata.len(),
+            day: modified / SECONDS_PER_DAY,
+            at_line_start: true,
+        })
+    }
+
+    fn needs_rotation(&self, len: usize) -> bool {
+        match CONFIG.log_rotation {
+            LogRotation::Size => self.size > 0 && self.size + len as u64 > CONFIG.log_max_size * 1024,
+            LogRotation::Daily => self.size > 0 && now_timestamp() / SECONDS_PER_DAY != self.day,
+        }
+    }
+
+    /// Number the current file and start a new one, the oldest file is deleted
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let max_files = CONFIG.log_max_files;
+        if max_files == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for i in (1..max_files).rev() {
+                let older = format!("{}.{}", self.path, i);
+                if fs::metadata(&older).is_ok() {
+                    fs::rename(&older, format!("{}.{}", self.path, i + 1))?;
+                }
+            }
+            fs::rename(&self.path, format!("{}.1", self.path))?;
+        }
+
+        *self = RotatingFile::open(&self.path)?;
+        self.day = now_timestamp() / SECONDS_PER_DAY;
+        Ok(())
+    }
+}
+
+// The file is written by a single logger, which writes each record under its lock
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.at_line_start && self.needs_rotation(buf.len()) {
+            self.rotate()?;
+        }
+
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        self.at_line_start = buf[..written].ends_with(b"\n");
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Logger applying the level of the module of each record before passing it to the outputs
+/// The outputs are a single logger each, so the records of different modules can't interleave
+struct ModuleLogger {
+    level: LevelFilter,
+    // Levels of the modules, the most specific module first
+    module_levels: Vec<(String, LevelFilter)>,
+    outputs: Vec<Box<dyn SharedLogger>>,
+}
+
+impl ModuleLogger {
+    fn level(&self, target: &str) -> LevelFilter {
+        self.module_levels
+            .iter()
+            .find(|(module, _)| target.starts_with(module.as_str()))
+            .map_or(self.level, |(_, level)| *level)
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        self.module_levels.iter().map(|(_, level)| *level).fold(self.level, Ord::max)
+    }
+}
+
+impl Log for ModuleLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            for output in &self.outputs {
+                output.log(record);
+            }
+        }
+    }
+
+    fn flush(&self) {
+        for output in &self.outputs {
+            output.flush();
+        }
+    }
+}
+
+fn output_config(file: bool) -> Config {
+    let mut builder = ConfigBuilder::new();
+    if file {
+        builder.set_time_format_rfc3339();
+    }
+    builder.build()
+}
+
+/// Start logging as configured, the logs of the modules without level use the global one
+pub fn init() {
+    let mut module_levels = CONFIG.log_module_levels.clone();
+    module_levels.sort_by_key(|(module, _)| std::cmp::Reverse(module.len()));
+    let mut logger = ModuleLogger { level: CONFIG.log_level, module_levels, outputs: Vec::new() };
+    let max_level = logger.max_level();
+
+    if CONFIG.log_output != LogOutput::File {
+        logger.outputs.push(TermLogger::new(max_level, output_config(false), TerminalMode::Stderr,
+                                            ColorChoice::Auto));
+    }
+    if CONFIG.log_output != LogOutput::Stderr {
+        match RotatingFile::open(&CONFIG.log_path) {
+            Ok(file) => logger.outputs.push(WriteLogger::new(max_level, output_config(true), file)),
+            // Nothing can be logged yet
+            Err(e) => panic!("An error occurred opening the log file {}: {}", CONFIG.log_path, e),
+        }
+    }
+
+    log::set_max_level(max_level);
+    log::set_boxed_logger(Box::new(logger)).unwrap();
+}
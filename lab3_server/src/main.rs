@@ -12,24 +12,61 @@ mod messages;
 mod access_control;
 mod user_connected;
 mod env_reader;
+mod two_factor;
+mod sms_sender;
+mod phone_verification;
+mod time_tools;
+mod hex_tools;
+mod session_tokens;
+mod session_registry;
+mod password_policy;
+mod pepper;
+mod audit;
+mod audit_sink;
+mod logging;
+mod metrics;
+mod health;
+mod shutdown;
+mod user_store;
+mod sqlite_store;
+mod journal;
+mod db_crypto;
+mod db_schema;
+mod backup;
 
 use crate::action::Action;
 use crate::user_connected::ConnectedUser;
 use crate::user::UserRole;
-use crate::database::Database;
-use crate::env_reader::read_env_file;
+use crate::database::{load_ron_users, rotate_key, DB_PATH};
+use crate::db_crypto::{config_key_source, generate_key_file, KeySource};
+use crate::sqlite_store::SqliteUserStore;
+use crate::user_store::{open_user_store, UserStore, UserStoreKind};
+use crate::env_reader::CONFIG;
+use crate::password_policy::{password_expiry, PasswordExpiry};
+use crate::audit::verify_audit_log;
+use crate::backup::{lock_database, lock_database_file, restore_backup};
+use crate::audit_sink::SyslogAddress;
+use crate::messages::SERVER_SHUTTING_DOWN;
+use crate::session_registry::set_session_busy;
+use crate::shutdown::is_shutting_down;
 use connection::Connection;
+use input_validation::secret::Secret;
 use lazy_static::lazy_static;
 use native_tls::{Identity, Protocol, TlsAcceptor};
 use rand::Rng;
+use std::env;
 use std::error::Error;
 use std::fs::File;
-use std::io::Read;
+use std::io::{self, ErrorKind, Read};
 use std::net::TcpListener;
+use std::path::Path;
+use std::process;
 use std::sync::Arc;
 use std::thread;
-use simplelog::{ColorChoice, Config, LevelFilter, TerminalMode, TermLogger};
-use log::{error, info, trace, warn};
+use std::time::Duration;
+use log::{info, trace, warn};
+
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(100);
 
 lazy_static! {
     static ref MOTIVATIONAL_QUOTES: Vec<&'static str> = vec![
@@ -44,18 +81,45 @@ lazy_static! {
 }
 
 // Handles client connection by sending a banner and then waiting for a client action
-fn handle_client(conn: Connection) -> Result<(), Box<dyn Error>> {
+// Once the server is shutting down, a notice is sent instead of the banner
+fn handle_client(conn: Connection, store: &dyn UserStore) -> Result<(), Box<dyn Error>> {
     trace!("Handling new client");
 
-    let mut u = ConnectedUser::anonymous(conn); // Anonymous user at first
+    let mut u = ConnectedUser::anonymous(conn)?; // Anonymous user at first
     loop {
+        if is_shutting_down() {
+            let notice: Result<String, &str> = Err(SERVER_SHUTTING_DOWN);
+            return u.conn().send(&notice);
+        }
+
         let mut banner = "Welcome to RESIGN (hR onlinE uSer dIrectory manaGemeNt)!".to_string();
         if !u.is_anonymous() {
             banner.push_str(
-                format!("\nCurrently logged in as {}", u.user_account()?.username()).as_str(),
+                format!("\nCurrently logged in as {}", u.user_account(store)?.username()).as_str(),
             );
 
-            if let UserRole::HR = u.user_account()?.role() {
+            let user = u.user_account(store)?;
+            if user.pending_phone().is_some() {
+                banner.push_str("\nYour new phone number is waiting for the PIN sent by SMS");
+            } else if !user.phone_verified() {
+                banner.push_str("\nYour phone number has not been verified");
+            }
+
+            match password_expiry(&user) {
+                PasswordExpiry::Valid => {}
+                PasswordExpiry::ExpiresSoon(1) => {
+                    banner.push_str("\nYour password expires in 1 day, please change it");
+                }
+                PasswordExpiry::ExpiresSoon(days) => {
+                    banner.push_str(format!("\nYour password expires in {} days, please change it",
+                                            days).as_str());
+                }
+                PasswordExpiry::Expired => {
+                    banner.push_str("\nYour password has expired, you can only change it or logout");
+                }
+            }
+
+            if let UserRole::HR = u.user_account(store)?.role() {
                 let quote =
                     MOTIVATIONAL_QUOTES[rand::thread_rng().gen_range(0..MOTIVATIONAL_QUOTES.len())];
                 banner.push_str(format!("\nQuote of the day: {}\n", quote).as_str());
@@ -63,94 +127,284 @@ fn handle_client(conn: Connection) -> Result<(), Box<dyn Error>> {
         }
 
         // We send the banner to  the client and we expect to receive an Action
+        let banner: Result<String, &str> = Ok(banner);
         u.conn().send(&banner)?;
-        let action = u.conn().receive::<Action>()?;
-        action.perform(&mut u)?;
+        set_session_busy(u.session_id(), false);
+        let action = match u.conn().receive::<Action>() {
+            Ok(action) => action,
+            // The connection is closed for reading when the server is shutting down
+            Err(_) if is_shutting_down() => continue,
+            Err(e) => return Err(e),
+        };
+        set_session_busy(u.session_id(), true);
+        u.touch();
+        action.perform(&mut u, store)?;
     }
 }
 
 // Load the server certificate and private key from PKCS8 format
-fn load_server_identity(cert_file: &str, key_file: &str) -> Identity {
+fn load_server_identity(cert_file: &str, key_file: &str) -> Result<Identity, Box<dyn Error>> {
     let mut cert = Vec::new();
     let mut key = Vec::new();
 
-    // No log cause the server crashes if it doesn't work
-    let mut cert_file = File::open(cert_file).expect("Certificate file not found");
-    let mut key_file = File::open(key_file).expect("Key file not found");
+    let mut cert_file = File::open(cert_file).map_err(|e| format!("Certificate file not found: {}", e))?;
+    let mut key_file = File::open(key_file).map_err(|e| format!("Key file not found: {}", e))?;
+
+    cert_file.read_to_end(&mut cert)?;
+    key_file.read_to_end(&mut key)?;
 
-    cert_file.read_to_end(&mut cert).unwrap();
-    key_file.read_to_end(&mut key).unwrap();
+    Ok(Identity::from_pkcs8(&cert, &key)?)
+}
 
-    Identity::from_pkcs8(&cert, &key).unwrap()
+/// Build the TLS acceptor from the server certificate and private key
+/// Also used by the health checks to check the TLS identity
+/// # Arguments
+/// * `cert_file` - path of the certificate
+/// * `key_file` - path of the private key
+/// # Returns
+/// * `Result<TlsAcceptor, Box<dyn Error>>` - The acceptor, an error if the identity is invalid
+pub fn build_tls_acceptor(cert_file: &str, key_file: &str) -> Result<TlsAcceptor, Box<dyn Error>> {
+    let identity = load_server_identity(cert_file, key_file)?;
+    Ok(TlsAcceptor::builder(identity)
+        .min_protocol_version(Some(Protocol::Tlsv12))
+        .max_protocol_version(None)
+        .build()?)
 }
 
 // Create a new TLS configuration
 fn tls_config(cert_file: &str, key_file: &str) -> Arc<TlsAcceptor> {
-    let identity = load_server_identity(cert_file, key_file);
-
     // No log cause the server crashes if it doesn't work
-    let acceptor = TlsAcceptor::builder(identity)
-        .min_protocol_version(Some(Protocol::Tlsv12))
-        .max_protocol_version(None)
-        .build()
-        .expect("Could not build TlsAcceptor");
+    let acceptor = build_tls_acceptor(cert_file, key_file).expect("Could not build TlsAcceptor");
 
     Arc::new(acceptor)
 }
 
-fn main() {
-    // Initialize logging policy
-    TermLogger::init(
-        LevelFilter::Warn,
-        Config::default(),
-        TerminalMode::Stderr,
-        ColorChoice::Auto
-    ).unwrap();
+// Verify the hash chain of the audit log given as argument or in the config
+fn verify_audit(path: Option<&String>) -> i32 {
+    let path = path.cloned().unwrap_or_else(|| CONFIG.audit_log_path.clone());
+    match verify_audit_log(&path) {
+        Ok((count, last_hash)) => {
+            println!("Audit log {} is valid: {} records, last hash: {}", path, count, last_hash);
+            0
+        }
+        Err(e) => {
+            println!("Audit log {} is corrupted: {}", path, e);
+            1
+        }
+    }
+}
+
+// Print the syslog messages received on the address given as argument
+fn audit_listener(address: Option<&String>) -> i32 {
+    let address: SyslogAddress = match address.map(|a| a.parse()) {
+        Some(Ok(address)) => address,
+        Some(Err(e)) => {
+            println!("{}", e);
+            return 1;
+        }
+        None => {
+            println!("Usage: audit-listener <udp://host:port | unix://path>");
+            return 1;
+        }
+    };
+    match audit_sink::listen(&address) {
+        Ok(()) => 0,
+        Err(e) => {
+            println!("Could not listen for syslog messages: {}", e);
+            1
+        }
+    }
+}
 
-    // Add default account in DB if file is not present
-    Database::init();
+// Copy the accounts of a RON file to the SQLite database of the config, which must have no account
+// Neither database must be used by a server
+fn import_ron(path: Option<&String>) -> i32 {
+    let path = path.map_or(DB_PATH, String::as_str);
+    let import = || -> Result<usize, Box<dyn Error>> {
+        let _ron_lock = lock_database_file(path)?;
+        let _sqlite_lock = lock_database_file(&CONFIG.sqlite_path)?;
+        let users = load_ron_users(path)?;
+        let store = SqliteUserStore::open(&CONFIG.sqlite_path)?;
+        if !store.is_empty()? {
+            return Err("The SQLite database already contains accounts".into());
+        }
+        store.transaction(&mut |tx| users.iter().try_for_each(|user| tx.insert(user)))?;
+        Ok(users.len())
+    };
 
-    // Get config infos from env file
-    let config = match read_env_file() {
-        Ok(config) => config,
+    match import() {
+        Ok(count) => {
+            println!("Imported {} accounts from {} to {}", count, path, CONFIG.sqlite_path);
+            0
+        }
+        Err(e) => {
+            println!("Could not import {} to {}: {}", path, CONFIG.sqlite_path, e);
+            1
+        }
+    }
+}
+
+// Re-encrypt the RON database with a key file, generated if it doesn't exist, with a passphrase read
+// from the standard input with `-`, or decrypt it with `none`. The database must not be used by a server
+fn rotate_db_key(new_key: Option<&String>) -> i32 {
+    let rotate = || -> Result<(usize, String), Box<dyn Error>> {
+        let _lock = lock_database()?;
+        if CONFIG.user_store != UserStoreKind::Ron {
+            return Err("Only the RON store can be encrypted".into());
+        }
+        let (new, setting) = match new_key.map(String::as_str) {
+            Some("none") => (None, "remove DB_KEY_PATH and DB_PASSPHRASE from the .env file".to_string()),
+            Some("-") => {
+                let mut line = String::new();
+                io::stdin().read_line(&mut line)?;
+                let line = Secret::new(line);
+                let passphrase = Secret::new(line.expose().trim_end_matches(['\r', '\n']).to_string());
+                if passphrase.expose().is_empty() {
+                    return Err("The passphrase can't be empty".into());
+                }
+                (Some(KeySource::Passphrase(passphrase)), "set DB_PASSPHRASE to the new passphrase in the .env file".to_string())
+            }
+            Some(path) => {
+                if !Path::new(path).exists() {
+                    generate_key_file(path)?;
+                    println!("New key written to {}", path);
+                }
+                (Some(KeySource::File(path.to_string())), format!("set DB_KEY_PATH={} in the .env file", path))
+            }
+            None => return Err("Usage: rotate-key <key file | - | none>".into()),
+        };
+        let count = rotate_key(DB_PATH, config_key_source().as_ref(), new.as_ref())?;
+        Ok((count, setting))
+    };
+
+    match rotate() {
+        Ok((count, setting)) => {
+            println!("Re-encrypted {} accounts in {}, {}", count, DB_PATH, setting);
+            0
+        }
         Err(e) => {
-            error!("An error occurred reading env file: {}", e);
-            panic!("An error occurred reading env file: {}", e)
+            println!("Could not re-encrypt {}: {}", DB_PATH, e);
+            1
+        }
+    }
+}
+
+// Replace the database of the config by the backup given as argument, after checking it
+fn restore(path: Option<&String>) -> i32 {
+    let path = match path {
+        Some(path) => path,
+        None => {
+            println!("Usage: restore <backup file>");
+            return 1;
         }
     };
+    match restore_backup(path) {
+        Ok((target, count)) => {
+            println!("Restored {} accounts from {} to {}", count, path, target);
+            0
+        }
+        Err(e) => {
+            println!("Could not restore {}: {}", path, e);
+            1
+        }
+    }
+}
+
+fn main() {
+    // Commands that don't start the server
+    let args: Vec<String> = env::args().collect();
+    if args.get(1).map(String::as_str) == Some("verify-audit") {
+        process::exit(verify_audit(args.get(2)));
+    }
+    if args.get(1).map(String::as_str) == Some("audit-listener") {
+        process::exit(audit_listener(args.get(2)));
+    }
+    if args.get(1).map(String::as_str) == Some("import-ron") {
+        process::exit(import_ron(args.get(2)));
+    }
+    if args.get(1).map(String::as_str) == Some("rotate-key") {
+        process::exit(rotate_db_key(args.get(2)));
+    }
+    if args.get(1).map(String::as_str) == Some("restore") {
+        process::exit(restore(args.get(2)));
+    }
+
+    // Initialize logging policy from the config
+    logging::init();
+
+    // Load the peppers and compute the password hashing parameters before any hash
+    pepper::init();
+    hashing_tools::init();
+
+    // Hold the lock of the database until the server stops, so that it can't be restored meanwhile
+    let _db_lock = lock_database().expect("Could not lock the database");
+
+    // Open the user accounts, with the default accounts if the DB file is not present
+    // No log cause the server crashes if it doesn't work
+    let store = open_user_store().expect("Could not open the database");
+
+    // Load the common and breached passwords
+    password_policy::init();
+
+    // Open the audit log and continue its hash chain
+    audit::init();
+
+    // Serve the metrics if enabled
+    metrics::init(store.clone());
+    backup::init(store.clone());
+
+    // Stop gracefully on SIGTERM or SIGINT
+    shutdown::init();
+
+    // Get config infos from env file
+    let config = &*CONFIG;
 
     // Start TLS server and wait for new connections
     let acceptor = tls_config(&config.certificate_path, &config.key_path);
-    let listener = TcpListener::bind(config.server_ip).unwrap();
+    let listener = TcpListener::bind(&config.server_ip).unwrap();
     //println!("Server started");
     info!("Server started");
 
+    // The listener doesn't block so that the shutdown signal is noticed
+    listener.set_nonblocking(true).unwrap();
+
     // Handles new connection, negotiate TLS and call handle_client
-    for stream in listener.incoming() {
-        info!("New connection");
-        match stream {
-            Ok(stream) => {
+    while !is_shutting_down() {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                info!("New connection");
+                if let Err(e) = stream.set_nonblocking(false) {
+                    warn!("Connection failed with error: {}", e);
+                    continue;
+                }
+                metrics::inc_connections();
                 let acceptor = acceptor.clone();
+                let store = store.clone();
                 thread::spawn(move || {
                     trace!("TLS handshake");
                     // TLS handshake on top of the connection using the TlsAcceptor
                     let stream = acceptor.accept(stream);
                     if stream.is_err() {
+                        metrics::inc_tls_handshake_failures();
                         warn!("TLS handshake failed with error: {}", stream.err().unwrap());
                     } else {
                         info!("TLS client connection accepted");
-                        if let Err(e) = handle_client(Connection::new(stream.unwrap())) {
+                        if let Err(e) = handle_client(Connection::new(stream.unwrap()), &*store) {
                             info!("Connection closed: {}", e);
                             return;
                         }
                     }
                 });
             }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(ACCEPT_POLL_INTERVAL),
             Err(e) => {
                 warn!("Connection failed with error: {}", e);
             }
         }
     }
 
+    // Stop accepting connections, then let the sessions finish
+    drop(listener);
+    shutdown::shutdown(&*store);
     info!("Server stopped");
 }
@@ -2,4 +2,21 @@
 pub const USER_EXISTS: &str = "User already exists";
 pub const USER_NOT_FOUND: &str = "Target user not found";
 pub const LOGIN_FAIL: &str = "Invalid user password combination!";
-pub const PERMISSION_DENIED: &str = "Permission denied: You don't have permission to perform this action";
\ No newline at end of file
+pub const PERMISSION_DENIED: &str = "Permission denied: You don't have permission to perform this action";
+pub const TWO_FACTOR_ALREADY_ENABLED: &str = "Two-factor authentication is already enabled";
+pub const TWO_FACTOR_NOT_ENABLED: &str = "Two-factor authentication is not enabled";
+pub const TWO_FACTOR_INVALID_CODE: &str = "Invalid two-factor code";
+pub const NO_PENDING_PHONE: &str = "No phone number change is pending";
+pub const PIN_EXPIRED: &str = "The PIN has expired, please change your phone number again";
+pub const WRONG_PIN: &str = "Wrong PIN";
+pub const PHONE_NUMBER_IN_USE: &str = "This phone number is already used by another account";
+pub const INVALID_SESSION_TOKEN: &str = "Invalid or expired session, please login again";
+pub const SESSION_NOT_FOUND: &str = "Session not found";
+pub const CANNOT_KILL_CURRENT_SESSION: &str = "The current session can't be terminated, logout instead";
+pub const WRONG_CURRENT_PASSWORD: &str = "The current password is wrong";
+pub const PASSWORD_REUSED: &str = "This password has already been used recently, please choose another one";
+pub const UNKNOWN_ACTION: &str = "Unknown action type";
+pub const INVALID_AUDIT_QUERY: &str = "Invalid time range or page number";
+pub const AUDIT_LOG_UNAVAILABLE: &str = "The audit log can't be read";
+pub const BACKUP_FAILED: &str = "The backup couldn't be made";
+pub const SERVER_SHUTTING_DOWN: &str = "The server is shutting down, please reconnect later";
\ No newline at end of file
@@ -0,0 +1,193 @@
+/// This file is used to expose the server metrics to Prometheus
+/// The metrics are served in the text format by an optional HTTP listener, on a loopback address only
+/// The same listener serves the liveness and readiness health checks
+use crate::audit::AuditOutcome;
+use crate::env_reader::CONFIG;
+use crate::health::{self, Health};
+use crate::session_registry::count_sessions;
+use crate::user_store::UserStore;
+use lazy_static::lazy_static;
+use log::{error, info, warn};
+use prometheus::{Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec,
+                 IntGaugeVec, Opts, Registry, TextEncoder};
+use std::error::Error;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const METRICS_PATH: &str = "/metrics";
+const LIVENESS_PATH: &str = "/health/live";
+const READINESS_PATH: &str = "/health/ready";
+const JSON_FORMAT: &str = "application/json";
+const HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+// Only the request line is read, the headers are ignored
+const MAX_REQUEST_LINE_LENGTH: u64 = 8192;
+
+lazy_static! {
+    static ref REGISTRY: Registry = Registry::new();
+
+    static ref ACTIVE_SESSIONS: IntGaugeVec = register(IntGaugeVec::new(
+        Opts::new("resign_active_sessions", "Connected sessions"), &["state"]).unwrap());
+    static ref LOGINS: IntCounterVec = register(IntCounterVec::new(
+        Opts::new("resign_logins_total", "Logins by outcome"), &["outcome"]).unwrap());
+    static ref ACTIONS: IntCounterVec = register(IntCounterVec::new(
+        Opts::new("resign_actions_total", "Actions by type and result"), &["action", "result"]).unwrap());
+    static ref PERMISSION_DENIALS: IntCounterVec = register(IntCounterVec::new(
+        Opts::new("resign_permission_denials_total", "Actions denied by the access control"), &["action"]).unwrap());
+    static ref ARGON2_SECONDS: HistogramVec = register(HistogramVec::new(
+        HistogramOpts::new("resign_argon2_seconds", "Duration of the Argon2 hashes and verifications")
+            .buckets(vec![0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0]),
+        &["operation"]).unwrap());
+    static ref TLS_HANDSHAKE_FAILURES: IntCounter = register(IntCounter::new(
+        "resign_tls_handshake_failures_total", "Failed TLS handshakes").unwrap());
+    static ref CONNECTIONS: IntCounter = register(IntCounter::new(
+        "resign_connections_total", "Accepted TCP connections").unwrap());
+    static ref DB_SAVE_SECONDS: Histogram = register(Histogram::with_opts(
+        HistogramOpts::new("resign_db_save_seconds", "Duration of the database saves")).unwrap());
+}
+
+// The metrics are defined once, a registration error is a bug
+fn register<T: prometheus::core::Collector + Clone + 'static>(metric: T) -> T {
+    REGISTRY.register(Box::new(metric.clone())).unwrap();
+    metric
+}
+
+fn outcome_label(outcome: AuditOutcome) -> &'static str {
+    match outcome {
+        AuditOutcome::Success => "success",
+        AuditOutcome::Failure => "failure",
+        AuditOutcome::Denied => "denied",
+    }
+}
+
+/// Count the result of an action, and the login or the permission denial it is
+/// # Arguments
+/// * `action` - name of the action
+/// * `outcome` - result sent to the client
+pub fn record_action(action: &str, outcome: AuditOutcome) {
+    ACTIONS.with_label_values(&[action, outcome_label(outcome)]).inc();
+    if action == "login" {
+        LOGINS.with_label_values(&[outcome_label(outcome)]).inc();
+    }
+    if outcome == AuditOutcome::Denied {
+        PERMISSION_DENIALS.with_label_values(&[action]).inc();
+    }
+}
+
+/// Measure the duration of an Argon2 operation
+/// # Arguments
+/// * `operation` - `hash` or `verify`
+/// * `start` - time at which the operation started
+pub fn observe_argon2(operation: &str, start: Instant) {
+    ARGON2_SECONDS.with_label_values(&[operation]).observe(start.elapsed().as_secs_f64());
+}
+
+/// Measure the duration of a database save
+pub fn observe_db_save(start: Instant) {
+    DB_SAVE_SECONDS.observe(start.elapsed().as_secs_f64());
+}
+
+pub fn inc_connections() {
+    CONNECTIONS.inc();
+}
+
+pub fn inc_tls_handshake_failures() {
+    TLS_HANDSHAKE_FAILURES.inc();
+}
+
+/// Encode every metric in the Prometheus text format
+pub fn gather() -> Result<String, Box<dyn Error>> {
+    let (anonymous, authenticated) = count_sessions();
+    ACTIVE_SESSIONS.with_label_values(&["anonymous"]).set(anonymous as i64);
+    ACTIVE_SESSIONS.with_label_values(&["authenticated"]).set(authenticated as i64);
+
+    let mut buffer = Vec::new();
+    TextEncoder::new().encode(&REGISTRY.gather(), &mut buffer)?;
+    Ok(String::from_utf8(buffer)?)
+}
+
+/// Check that an address can be used by the metrics listener
+/// # Arguments
+/// * `address` - address of the listener
+/// # Returns
+/// * `Result<SocketAddr, Box<dyn Error>>` - The address if it is a loopback address
+pub fn parse_metrics_address(address: &str) -> Result<SocketAddr, Box<dyn Error>> {
+    let address: SocketAddr = address.parse()?;
+    if !address.ip().is_loopback() {
+        return Err("The metrics address must be a loopback address".into());
+    }
+    Ok(address)
+}
+
+// A healthy server answers 200 and an unhealthy one 503, with the state of each component
+fn health_response(health: Health) -> Result<(&'static str, &'static str, String), Box<dyn Error>> {
+    let status = if health.healthy { "200 OK" } else { "503 Service Unavailable" };
+    Ok((status, JSON_FORMAT, serde_json::to_string(&health)?))
+}
+
+// Answer a single HTTP request, only GET is served
+fn handle_request(mut stream: TcpStream, store: &dyn UserStore) -> Result<(), Box<dyn Error>> {
+    stream.set_read_timeout(Some(HTTP_TIMEOUT))?;
+    stream.set_write_timeout(Some(HTTP_TIMEOUT))?;
+
+    let mut request_line = String::new();
+    BufReader::new((&stream).take(MAX_REQUEST_LINE_LENGTH)).read_line(&mut request_line)?;
+
+    let mut parts = request_line.split_whitespace();
+    let (status, content_type, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some(METRICS_PATH)) => ("200 OK", prometheus::TEXT_FORMAT, gather()?),
+        (Some("GET"), Some(LIVENESS_PATH)) => health_response(health::liveness())?,
+        (Some("GET"), Some(READINESS_PATH)) => health_response(health::readiness(store))?,
+        (Some("GET"), _) => ("404 Not Found", "text/plain", "Not found\n".to_string()),
+        _ => ("405 Method Not Allowed", "text/plain", "Method not allowed\n".to_string()),
+    };
+
+    write!(stream, "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+           status, content_type, body.len(), body)?;
+    Ok(())
+}
+
+/// Register the metrics and start the metrics listener if an address is given in the config
+/// # Arguments
+/// * `store` - store of the user accounts, checked by the readiness health check
+pub fn init(store: Arc<dyn UserStore>) {
+    // The metrics not updated yet are served too
+    lazy_static::initialize(&ACTIVE_SESSIONS);
+    lazy_static::initialize(&LOGINS);
+    lazy_static::initialize(&ACTIONS);
+    lazy_static::initialize(&PERMISSION_DENIALS);
+    lazy_static::initialize(&ARGON2_SECONDS);
+    lazy_static::initialize(&TLS_HANDSHAKE_FAILURES);
+    lazy_static::initialize(&CONNECTIONS);
+    lazy_static::initialize(&DB_SAVE_SECONDS);
+
+    let address = match CONFIG.metrics_address {
+        Some(address) => address,
+        None => return,
+    };
+
+    let listener = match TcpListener::bind(address) {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("An error occurred starting the metrics listener: {}", e);
+            panic!("An error occurred starting the metrics listener: {}", e)
+        }
+    };
+    info!("Metrics served on http://{}{}, health checks on {} and {}",
+        address, METRICS_PATH, LIVENESS_PATH, READINESS_PATH);
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if let Err(e) = handle_request(stream, &*store) {
+                        warn!("An error occurred serving the metrics: {}", e);
+                    }
+                }
+                Err(e) => warn!("Metrics connection failed with error: {}", e),
+            }
+        }
+    });
+}
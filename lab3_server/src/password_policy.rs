@@ -0,0 +1,109 @@
+/// This file is used to enforce the password policy whenever a password is set
+/// Weak, common or breached passwords are refused, the last passwords of an user can't be used again
+/// and passwords expire after a while
+use crate::env_reader::CONFIG;
+use crate::hashing_tools::{new_password_hash, verify_password};
+use crate::messages::PASSWORD_REUSED;
+use crate::time_tools::now_timestamp;
+use crate::user::UserAccount;
+use input_validation::messages::{PASSWORD_BLOCKLISTED, PASSWORD_CONTAINS_USERNAME, PASSWORD_TOO_WEAK};
+use input_validation::password::{estimate_password_strength, password_contains_username};
+use input_validation::password_blocklist::PasswordBlocklist;
+use input_validation::secret::Secret;
+use lazy_static::lazy_static;
+use log::{error, info};
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+lazy_static! {
+    // The password policy can't be enforced without the blocklist
+    static ref PASSWORD_BLOCKLIST: PasswordBlocklist =
+        match PasswordBlocklist::load(&CONFIG.password_blocklist_path) {
+            Ok(blocklist) => blocklist,
+            Err(e) => {
+                error!("An error occurred reading the password blocklist: {}", e);
+                panic!("An error occurred reading the password blocklist: {}", e)
+            }
+        };
+}
+
+/// State of the password of an user regarding its maximum age
+pub enum PasswordExpiry {
+    Valid,
+    // Number of days before the expiration, rounded up
+    ExpiresSoon(u64),
+    Expired,
+}
+
+/// Check if a password is the current password or one of the previous ones of an user
+/// # Arguments
+/// * `user` - user account to check
+/// * `password` - password to check
+/// # Returns
+/// * `bool` - True if the password has already been used, false otherwise
+pub fn is_password_reused(user: &UserAccount, password: &Secret<String>) -> bool {
+    verify_password(&user.password_hash(), password)
+        || user.password_history().iter().any(|hash| verify_password(hash, password))
+}
+
+/// Load the password blocklist
+pub fn init() {
+    info!("{} passwords loaded in the blocklist", PASSWORD_BLOCKLIST.len());
+}
+
+/// Check if a new password respects the password policy, regardless of the previous passwords
+/// # Arguments
+/// * `username` - username of the account
+/// * `password` - new password, already validated
+/// # Returns
+/// * `Result<(), &str>` - The error message to send to the client if the password is refused
+pub fn check_new_password(username: &str, password: &Secret<String>) -> Result<(), &'static str> {
+    let password = password.expose();
+    if password_contains_username(password, username) {
+        return Err(PASSWORD_CONTAINS_USERNAME);
+    }
+    if PASSWORD_BLOCKLIST.contains(password) {
+        return Err(PASSWORD_BLOCKLISTED);
+    }
+    if estimate_password_strength(password).score < CONFIG.password_min_score {
+        return Err(PASSWORD_TOO_WEAK);
+    }
+    Ok(())
+}
+
+/// Set a new password for an user if it respects the password policy
+/// # Arguments
+/// * `user` - user account to update
+/// * `password` - new password, already validated
+/// # Returns
+/// * `Result<(), &str>` - The error message to send to the client if the password is refused
+pub fn set_password(user: &mut UserAccount, password: &Secret<String>) -> Result<(), &'static str> {
+    check_new_password(user.username(), password)?;
+    if is_password_reused(user, password) {
+        return Err(PASSWORD_REUSED);
+    }
+
+    user.set_password(new_password_hash(password), CONFIG.password_history_size);
+    Ok(())
+}
+
+/// Check if the password of an user has expired or will expire soon
+/// # Arguments
+/// * `user` - user account to check
+/// # Returns
+/// * `PasswordExpiry` - The state of the password
+pub fn password_expiry(user: &UserAccount) -> PasswordExpiry {
+    if CONFIG.password_max_age == 0 {
+        return PasswordExpiry::Valid;
+    }
+
+    let expires_at = user.password_changed_at() + CONFIG.password_max_age * SECONDS_PER_DAY;
+    let now = now_timestamp();
+    if now >= expires_at {
+        PasswordExpiry::Expired
+    } else if expires_at - now <= CONFIG.password_expiry_warning * SECONDS_PER_DAY {
+        PasswordExpiry::ExpiresSoon((expires_at - now).div_ceil(SECONDS_PER_DAY))
+    } else {
+        PasswordExpiry::Valid
+    }
+}
@@ -0,0 +1,86 @@
+/// This file is used to load the server-side peppers mixed into the password hashes
+/// The peppers are never stored in the database, only in the key file given in the config
+/// The key file contains one pepper per line with its version: `<version>:<secret in hex>`
+/// The highest version is used for the new hashes, the others are kept to verify the older ones
+use crate::env_reader::CONFIG;
+use crate::hex_tools::from_hex;
+use lazy_static::lazy_static;
+use log::{error, info};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+
+// Version of the hashes made without pepper
+pub const NO_PEPPER_VERSION: u32 = 0;
+const MIN_PEPPER_LENGTH: usize = 16;
+
+lazy_static! {
+    // The password hashes can't be verified without their pepper
+    static ref PEPPERS: BTreeMap<u32, Vec<u8>> = match &CONFIG.pepper_path {
+        Some(path) => match load_peppers(path) {
+            Ok(peppers) => peppers,
+            Err(e) => {
+                error!("An error occurred reading the pepper file: {}", e);
+                panic!("An error occurred reading the pepper file: {}", e)
+            }
+        },
+        None => BTreeMap::new(),
+    };
+}
+
+/// Load the peppers from a key file
+/// Empty lines and lines starting with # are ignored
+/// # Arguments
+/// * `path` - path of the key file
+/// # Returns
+/// * `Result<BTreeMap<u32, Vec<u8>>, Box<dyn Error>>` - The peppers by version
+pub fn load_peppers(path: &str) -> Result<BTreeMap<u32, Vec<u8>>, Box<dyn Error>> {
+    let mut peppers = BTreeMap::new();
+    for line in fs::read_to_string(path)?.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (version, secret) = line.split_once(':').ok_or("Invalid pepper line")?;
+        let version: u32 = version.trim().parse()?;
+        let secret = from_hex(secret.trim()).ok_or("Invalid pepper, it must be hexadecimal")?;
+        if version == NO_PEPPER_VERSION {
+            return Err(format!("Pepper version {} is reserved", NO_PEPPER_VERSION).into());
+        }
+        if secret.len() < MIN_PEPPER_LENGTH {
+            return Err(format!("Pepper {} is too short, it needs at least {} bytes",
+                               version, MIN_PEPPER_LENGTH).into());
+        }
+        if peppers.insert(version, secret).is_some() {
+            return Err(format!("Pepper {} is defined twice", version).into());
+        }
+    }
+    Ok(peppers)
+}
+
+/// Load the peppers at startup
+pub fn init() {
+    info!("{} peppers loaded, current version: {}", PEPPERS.len(), current_pepper().0);
+}
+
+/// Get the pepper used for the new hashes
+/// # Returns
+/// * `(u32, &[u8])` - The version and the pepper, an empty pepper if there is none
+pub fn current_pepper() -> (u32, &'static [u8]) {
+    match PEPPERS.iter().next_back() {
+        Some((version, pepper)) => (*version, pepper),
+        None => (NO_PEPPER_VERSION, &[]),
+    }
+}
+
+/// Get the pepper of a given version
+/// # Arguments
+/// * `version` - version of the pepper
+/// # Returns
+/// * `Option<&[u8]>` - The pepper if it is known, an empty pepper for the hashes made without
+pub fn pepper(version: u32) -> Option<&'static [u8]> {
+    if version == NO_PEPPER_VERSION {
+        return Some(&[]);
+    }
+    PEPPERS.get(&version).map(Vec::as_slice)
+}
@@ -0,0 +1,80 @@
+/// This file is used to verify the phone numbers of the users
+/// A random PIN is sent by SMS to the new phone number and the change stays pending
+/// until the PIN is confirmed by the user
+use crate::hashing_tools::{new_hash_password, verify_hash};
+use crate::sms_sender::sms_sender;
+use crate::time_tools::now_timestamp;
+use crate::user::{PendingPhone, UserAccount};
+use rand::Rng;
+use std::error::Error;
+
+const PIN_LENGTH: usize = 6;
+const PIN_VALIDITY_SECONDS: u64 = 10 * 60;
+const PIN_MAX_ATTEMPTS: u8 = 3;
+
+/// Result of the confirmation of a pending phone number
+pub enum PhoneConfirmation {
+    Confirmed,
+    InvalidPin,
+    Expired,
+    NothingPending,
+}
+
+fn new_pin() -> String {
+    let mut rng = rand::thread_rng();
+    (0..PIN_LENGTH)
+        .map(|_| char::from(b'0' + rng.gen_range(0..10)))
+        .collect()
+}
+
+/// Send a PIN to a new phone number and store the change as pending in the user account
+/// The user account must be saved by the caller
+/// # Arguments
+/// * `user` - user account changing his phone number
+/// * `phone_number` - new phone number to verify
+pub fn start_phone_verification(user: &mut UserAccount, phone_number: String) -> Result<(), Box<dyn Error>> {
+    let pin = new_pin();
+    let pin_hash = new_hash_password(&pin);
+
+    sms_sender().send(&phone_number, &format!("Your RESIGN verification PIN is: {}", pin))?;
+    user.set_pending_phone(Some(PendingPhone::new(
+        phone_number,
+        pin_hash,
+        now_timestamp() + PIN_VALIDITY_SECONDS,
+        PIN_MAX_ATTEMPTS,
+    )));
+    Ok(())
+}
+
+/// Confirm the pending phone number of an user with the PIN he received
+/// The pending change is dropped once expired or after too many invalid PIN
+/// The user account must be saved by the caller
+/// # Arguments
+/// * `user` - user account confirming his phone number
+/// * `pin` - PIN given by the user
+/// # Returns
+/// * `PhoneConfirmation` - The result of the confirmation
+pub fn confirm_phone_number(user: &mut UserAccount, pin: &str) -> PhoneConfirmation {
+    let pending = match user.pending_phone_mut() {
+        Some(pending) => pending,
+        None => return PhoneConfirmation::NothingPending,
+    };
+
+    if pending.expires_at() < now_timestamp() {
+        user.set_pending_phone(None);
+        return PhoneConfirmation::Expired;
+    }
+
+    if verify_hash(pending.pin_hash(), pin) {
+        let phone_number = pending.phone_number().to_string();
+        user.set_pending_phone(None);
+        user.set_verified_phone_number(phone_number);
+        PhoneConfirmation::Confirmed
+    } else {
+        pending.decrease_attempts();
+        if pending.attempts_left() == 0 {
+            user.set_pending_phone(None);
+        }
+        PhoneConfirmation::InvalidPin
+    }
+}
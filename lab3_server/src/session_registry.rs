@@ -0,0 +1,196 @@
+/// This file is used to keep track of the sessions connected to the server
+/// Each connection is registered with the user logged in, the peer IP, the login time
+/// and the last activity so the sessions can be listed and terminated
+use crate::session_tokens::revoke_session_token;
+use crate::time_tools::now_timestamp;
+use lazy_static::lazy_static;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::{Shutdown, TcpStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+lazy_static! {
+    static ref SESSIONS: Mutex<HashMap<u64, Session>> = Mutex::new(HashMap::new());
+}
+
+static NEXT_SESSION_ID: AtomicU64 = AtomicU64::new(1);
+
+struct Session {
+    username: Option<String>,
+    session_token: Option<String>,
+    peer_ip: String,
+    login_time: Option<u64>,
+    last_activity: u64,
+    // True while an action is performed, false while waiting for the next one
+    busy: bool,
+    // Clone of the socket used to close the connection
+    socket: TcpStream,
+}
+
+/// Information about a session sent to the clients
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SessionInfo {
+    pub id: u64,
+    pub username: Option<String>,
+    pub peer_ip: String,
+    pub login_time: Option<u64>,
+    pub last_activity: u64,
+    pub current: bool,
+}
+
+/// Register a new connection
+/// # Arguments
+/// * `peer_ip` - IP address of the client
+/// * `socket` - clone of the socket of the connection
+/// # Returns
+/// * `u64` - The id of the session
+pub fn register_session(peer_ip: String, socket: TcpStream) -> u64 {
+    let id = NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed);
+    SESSIONS.lock().unwrap().insert(id, Session {
+        username: None,
+        session_token: None,
+        peer_ip,
+        login_time: None,
+        last_activity: now_timestamp(),
+        busy: false,
+        socket,
+    });
+    id
+}
+
+/// Remove a session once its connection is closed
+pub fn unregister_session(id: u64) {
+    SESSIONS.lock().unwrap().remove(&id);
+}
+
+/// Set the user logged in a session, none on logout
+/// # Arguments
+/// * `id` - id of the session
+/// * `username` - user logged in
+/// * `session_token` - session token given to the user
+pub fn set_session_user(id: u64, username: Option<&str>, session_token: Option<&str>) {
+    if let Some(session) = SESSIONS.lock().unwrap().get_mut(&id) {
+        session.username = username.map(str::to_string);
+        session.session_token = session_token.map(str::to_string);
+        session.login_time = username.map(|_| now_timestamp());
+    }
+}
+
+/// Mark a session as performing an action or waiting for the next one
+pub fn set_session_busy(id: u64, busy: bool) {
+    if let Some(session) = SESSIONS.lock().unwrap().get_mut(&id) {
+        session.busy = busy;
+    }
+}
+
+/// Stop reading from the sessions waiting for an action, their thread can still answer them
+/// The sessions performing an action are left to finish it
+/// # Returns
+/// * `usize` - The number of sessions still connected
+pub fn close_idle_sessions() -> usize {
+    let sessions = SESSIONS.lock().unwrap();
+    for (id, session) in sessions.iter().filter(|(_, s)| !s.busy) {
+        if let Err(e) = session.socket.shutdown(Shutdown::Read) {
+            warn!("Could not close the connection of session {}: {}", id, e);
+        }
+    }
+    sessions.len()
+}
+
+/// Close the connection of every session
+pub fn close_all_sessions() {
+    for (id, session) in SESSIONS.lock().unwrap().iter() {
+        if let Err(e) = session.socket.shutdown(Shutdown::Both) {
+            warn!("Could not close the connection of session {}: {}", id, e);
+        }
+    }
+}
+
+/// Count the sessions connected
+/// # Returns
+/// * `(usize, usize)` - The number of anonymous and authenticated sessions
+pub fn count_sessions() -> (usize, usize) {
+    let sessions = SESSIONS.lock().unwrap();
+    let authenticated = sessions.values().filter(|s| s.username.is_some()).count();
+    (sessions.len() - authenticated, authenticated)
+}
+
+/// Update the last activity of a session
+pub fn touch_session(id: u64) {
+    if let Some(session) = SESSIONS.lock().unwrap().get_mut(&id) {
+        session.last_activity = now_timestamp();
+    }
+}
+
+/// List the sessions connected
+/// # Arguments
+/// * `current_id` - id of the session asking the list
+/// * `username` - only list the sessions of this user, all sessions if none
+/// # Returns
+/// * `Vec<SessionInfo>` - The sessions sorted by id
+pub fn list_sessions(current_id: u64, username: Option<&str>) -> Vec<SessionInfo> {
+    let sessions = SESSIONS.lock().unwrap();
+    let mut infos: Vec<SessionInfo> = sessions
+        .iter()
+        .filter(|(_, s)| username.is_none() || s.username.as_deref() == username)
+        .map(|(id, s)| SessionInfo {
+            id: *id,
+            username: s.username.clone(),
+            peer_ip: s.peer_ip.clone(),
+            login_time: s.login_time,
+            last_activity: s.last_activity,
+            current: *id == current_id,
+        })
+        .collect();
+    infos.sort_by_key(|s| s.id);
+    infos
+}
+
+/// Terminate a session, its session token is revoked and its connection closed
+/// # Arguments
+/// * `id` - id of the session to terminate
+/// * `username` - only terminate the session if it belongs to this user, any session if none
+/// # Returns
+/// * `bool` - True if the session has been terminated, false if not found
+pub fn kill_session(id: u64, username: Option<&str>) -> bool {
+    let mut sessions = SESSIONS.lock().unwrap();
+    match sessions.get(&id) {
+        Some(s) if username.is_none() || s.username.as_deref() == username => {
+            terminate(id, sessions.remove(&id).unwrap());
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Terminate every session of an user, their session tokens are revoked and their connections closed
+/// # Arguments
+/// * `username` - user whose sessions are terminated
+/// * `except_id` - id of a session to keep, if any
+/// # Returns
+/// * `usize` - The number of sessions terminated
+pub fn kill_user_sessions(username: &str, except_id: Option<u64>) -> usize {
+    let mut sessions = SESSIONS.lock().unwrap();
+    let ids: Vec<u64> = sessions
+        .iter()
+        .filter(|(id, s)| s.username.as_deref() == Some(username) && Some(**id) != except_id)
+        .map(|(id, _)| *id)
+        .collect();
+    for id in &ids {
+        terminate(*id, sessions.remove(id).unwrap());
+    }
+    ids.len()
+}
+
+fn terminate(id: u64, session: Session) {
+    if let Some(token) = &session.session_token {
+        revoke_session_token(token);
+    }
+    // The thread handling the connection stops once the socket is closed
+    if let Err(e) = session.socket.shutdown(Shutdown::Both) {
+        warn!("Could not close the connection of session {}: {}", id, e);
+    }
+    info!("Session {} from {} terminated", id, session.peer_ip);
+}
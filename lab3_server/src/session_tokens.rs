@@ -0,0 +1,104 @@
+/// This file is used to store the session tokens given to the users at login
+/// A session token allows a client to resume its session on a new connection
+/// Only the hash of the tokens are stored on the server side
+use crate::env_reader::CONFIG;
+use crate::hex_tools::to_hex;
+use crate::time_tools::now_timestamp;
+use lazy_static::lazy_static;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+const SESSION_TOKEN_LENGTH: usize = 32;
+
+lazy_static! {
+    static ref SESSION_TOKENS: Mutex<HashMap<String, SessionToken>> = Mutex::new(HashMap::new());
+}
+
+struct SessionToken {
+    username: String,
+    expires_at: u64,
+}
+
+fn hash_token(token: &str) -> String {
+    to_hex(&Sha256::digest(token.as_bytes()))
+}
+
+/// Issue a new random session token for an user
+/// # Arguments
+/// * `username` - user logged in
+/// # Returns
+/// * `String` - The session token to give to the client
+pub fn issue_session_token(username: &str) -> String {
+    let mut bytes = [0u8; SESSION_TOKEN_LENGTH];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let token = to_hex(&bytes);
+
+    let now = now_timestamp();
+    let mut tokens = SESSION_TOKENS.lock().unwrap();
+    // Drop the expired tokens
+    tokens.retain(|_, t| t.expires_at > now);
+    tokens.insert(hash_token(&token), SessionToken {
+        username: username.to_string(),
+        expires_at: now + CONFIG.session_token_lifetime,
+    });
+
+    token
+}
+
+/// Validate a session token
+/// # Arguments
+/// * `token` - session token given by the client
+/// # Returns
+/// * `Option<String>` - The username of the session if the token is valid and not expired
+pub fn validate_session_token(token: &str) -> Option<String> {
+    let mut tokens = SESSION_TOKENS.lock().unwrap();
+    let hash = hash_token(token);
+    match tokens.get(&hash) {
+        Some(t) if t.expires_at > now_timestamp() => Some(t.username.clone()),
+        Some(_) => {
+            tokens.remove(&hash);
+            None
+        }
+        None => None,
+    }
+}
+
+/// Revoke a session token
+/// # Arguments
+/// * `token` - session token to revoke
+pub fn revoke_session_token(token: &str) {
+    SESSION_TOKENS.lock().unwrap().remove(&hash_token(token));
+}
+
+/// Revoke every session token of an user, e.g. once his password has changed
+/// # Arguments
+/// * `username` - user whose session tokens are revoked
+/// * `keep` - session token of the current session to keep, if any
+pub fn revoke_user_session_tokens(username: &str, keep: Option<&str>) {
+    let keep = keep.map(hash_token);
+    SESSION_TOKENS.lock().unwrap()
+        .retain(|hash, t| t.username != username || keep.as_ref() == Some(hash));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_revoke_user_session_tokens() {
+        let old = issue_session_token("token-test-alice");
+        let current = issue_session_token("token-test-alice");
+        let other = issue_session_token("token-test-bob");
+        assert_eq!(validate_session_token(&old).as_deref(), Some("token-test-alice"));
+
+        revoke_user_session_tokens("token-test-alice", Some(&current));
+        assert!(validate_session_token(&old).is_none());
+        assert_eq!(validate_session_token(&current).as_deref(), Some("token-test-alice"));
+        assert_eq!(validate_session_token(&other).as_deref(), Some("token-test-bob"));
+
+        revoke_user_session_tokens("token-test-alice", None);
+        assert!(validate_session_token(&current).is_none());
+    }
+}
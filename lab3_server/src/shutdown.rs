@@ -0,0 +1,86 @@
+/// This file is used to stop the server gracefully on SIGTERM or SIGINT
+/// The server stops accepting connections, tells the clients it is shutting down and lets the actions
+/// in progress finish until the deadline. Then the changes of the accounts are stopped, the remaining
+/// sessions are cut off and the database and the audit log are flushed
+use crate::audit;
+use crate::env_reader::CONFIG;
+use crate::session_registry::{close_all_sessions, close_idle_sessions};
+use crate::user_store::{close_writes, UserStore};
+use log::{error, info, warn};
+use std::error::Error;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::signal::unix::{signal, SignalKind};
+
+const SESSIONS_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+
+/// Check if the server is shutting down
+pub fn is_shutting_down() -> bool {
+    SHUTTING_DOWN.load(Ordering::SeqCst)
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn wait_for_signal() -> Result<&'static str, Box<dyn Error>> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    tokio::select! {
+        _ = terminate.recv() => Ok("SIGTERM"),
+        _ = interrupt.recv() => Ok("SIGINT"),
+    }
+}
+
+/// Wait for the shutdown signals in the background
+/// The first signal starts the graceful shutdown, a second one stops the server immediately
+pub fn init() {
+    thread::spawn(|| {
+        match wait_for_signal() {
+            Ok(signal) => {
+                info!("{} received, shutting down", signal);
+                SHUTTING_DOWN.store(true, Ordering::SeqCst);
+            }
+            Err(e) => {
+                error!("An error occurred waiting for the shutdown signals: {}", e);
+                return;
+            }
+        }
+
+        if let Ok(signal) = wait_for_signal() {
+            warn!("{} received again, stopping immediately", signal);
+            process::exit(1);
+        }
+    });
+}
+
+/// Close the sessions and flush the data, once the server doesn't accept connections anymore
+/// The sessions still performing an action at the deadline are cut off, once no change of the
+/// accounts is in progress
+pub fn shutdown(store: &dyn UserStore) {
+    let deadline = Instant::now() + Duration::from_secs(CONFIG.shutdown_timeout);
+    let remaining = loop {
+        // The idle sessions are closed again in case they were busy the previous time
+        let remaining = close_idle_sessions();
+        if remaining == 0 || Instant::now() >= deadline {
+            break remaining;
+        }
+        thread::sleep(SESSIONS_POLL_INTERVAL);
+    };
+
+    // The store isn't changed by the sessions left while it is flushed
+    close_writes();
+    if remaining > 0 {
+        warn!("{} sessions still connected at the shutdown deadline, closing them", remaining);
+        close_all_sessions();
+    }
+
+    if let Err(e) = store.flush() {
+        error!("An error occurred saving the database: {}", e);
+    }
+    if let Err(e) = audit::flush() {
+        error!("An error occurred flushing the audit log: {}", e);
+    }
+    log::logger().flush();
+}
@@ -0,0 +1,49 @@
+/// This file is used to send SMS to the users
+/// The sender is pluggable, only a file backed sender is available for development
+use crate::env_reader::CONFIG;
+use crate::time_tools::now_timestamp;
+use lazy_static::lazy_static;
+use log::info;
+use std::error::Error;
+use std::fs::OpenOptions;
+use std::io::Write;
+
+lazy_static! {
+    static ref SMS_SENDER: Box<dyn SmsSender> =
+        Box::new(FileSmsSender::new(&CONFIG.sms_outbox_path));
+}
+
+/// Used to send a SMS to a phone number
+pub trait SmsSender: Send + Sync {
+    fn send(&self, phone_number: &str, message: &str) -> Result<(), Box<dyn Error>>;
+}
+
+/// Development sender, the SMS are appended to a file instead of being sent
+pub struct FileSmsSender {
+    path: String,
+}
+
+impl FileSmsSender {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: path.to_string(),
+        }
+    }
+}
+
+impl SmsSender for FileSmsSender {
+    fn send(&self, phone_number: &str, message: &str) -> Result<(), Box<dyn Error>> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "[{}] To {}: {}", now_timestamp(), phone_number, message)?;
+        info!("SMS written to {} for phone number {}", self.path, phone_number);
+        Ok(())
+    }
+}
+
+/// Get the SMS sender configured for the server
+pub fn sms_sender() -> &'static dyn SmsSender {
+    SMS_SENDER.as_ref()
+}
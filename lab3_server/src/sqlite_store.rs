@@ -0,0 +1,254 @@
+/// This file is used to store the user accounts in a SQLite database
+/// Each account is a row with the columns used to look it up and the whole account as JSON
+/// The schema is versioned with `PRAGMA user_version` and migrated when the store is opened
+use crate::metrics::observe_db_save;
+use crate::user::{UserAccount, UserRole};
+use crate::user_store::{begin_write, TransactionTask, UserStore, UserTransaction};
+use log::info;
+use rusqlite::{params, Connection, OptionalExtension, Params};
+use std::error::Error;
+use std::fs::File;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Instant;
+
+// Each migration brings the schema from the version of its index to the next one
+const MIGRATIONS: &[&str] = &[
+    // 1: accounts
+    "CREATE TABLE users (
+        id INTEGER PRIMARY KEY,
+        username TEXT NOT NULL,
+        phone_number TEXT NOT NULL,
+        role TEXT NOT NULL,
+        account TEXT NOT NULL
+    );",
+    // 2: lookups by username and phone number
+    "CREATE UNIQUE INDEX users_username ON users (username);
+     CREATE INDEX users_phone_number ON users (phone_number);",
+];
+
+const POISONED_STORE: &str = "The SQLite database is unusable after a panic";
+
+/// Store of the user accounts in a SQLite file
+pub struct SqliteUserStore {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteUserStore {
+    /// Open the database, it is created if it doesn't exist and its schema is migrated
+    /// # Arguments
+    /// * `path` - path of the SQLite file
+    pub fn open(path: &str) -> Result<SqliteUserStore, Box<dyn Error>> {
+        let mut conn = Connection::open(path)?;
+        migrate(&mut conn)?;
+        Ok(SqliteUserStore { conn: Mutex::new(conn) })
+    }
+
+    /// Check if the database has no account
+    pub fn is_empty(&self) -> Result<bool, Box<dyn Error>> {
+        let exists: bool = self.conn()?.query_row("SELECT EXISTS (SELECT 1 FROM users)", [], |row| row.get(0))?;
+        Ok(!exists)
+    }
+
+    fn conn(&self) -> Result<MutexGuard<'_, Connection>, Box<dyn Error>> {
+        Ok(self.conn.lock().map_err(|_| POISONED_STORE)?)
+    }
+}
+
+// Apply the migrations not applied yet, each one in its own transaction
+fn migrate(conn: &mut Connection) -> Result<(), Box<dyn Error>> {
+    let version: u32 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    let version = version as usize;
+    if version > MIGRATIONS.len() {
+        return Err(format!("The SQLite database has schema version {}, this server supports up to {}",
+                           version, MIGRATIONS.len()).into());
+    }
+
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", (i + 1) as u32)?;
+        tx.commit()?;
+        info!("SQLite database migrated to schema version {}", i + 1);
+    }
+    Ok(())
+}
+
+fn role_string(role: &UserRole) -> &'static str {
+    match role {
+        UserRole::StandardUser => "StandardUser",
+        UserRole::HR => "HR",
+    }
+}
+
+fn get_user(conn: &Connection, username: &str) -> Result<Option<UserAccount>, Box<dyn Error>> {
+    let account: Option<String> = conn
+        .query_row("SELECT account FROM users WHERE username = ?1", params![username], |row| row.get(0))
+        .optional()?;
+    Ok(match account {
+        Some(account) => Some(serde_json::from_str(&account)?),
+        None => None,
+    })
+}
+
+fn insert_user(conn: &Connection, user: &UserAccount) -> Result<(), Box<dyn Error>> {
+    conn.execute(
+        "INSERT INTO users (username, phone_number, role, account) VALUES (?1, ?2, ?3, ?4)
+         ON CONFLICT (username) DO UPDATE SET
+            phone_number = excluded.phone_number, role = excluded.role, account = excluded.account",
+        params![user.username(), user.phone_number(), role_string(user.role()), serde_json::to_string(user)?],
+    )?;
+    Ok(())
+}
+
+// Accounts selected by a query of their `account` column
+fn query_users<P: Params>(conn: &Connection, sql: &str, params: P) -> Result<Vec<UserAccount>, Box<dyn Error>> {
+    let mut statement = conn.prepare(sql)?;
+    let accounts = statement.query_map(params, |row| row.get::<_, String>(0))?;
+    let mut users = Vec::new();
+    for account in accounts {
+        users.push(serde_json::from_str(&account?)?);
+    }
+    Ok(users)
+}
+
+/// Transaction of SQLite, committed only if the task succeeds
+struct SqliteTransaction<'a> {
+    tx: rusqlite::Transaction<'a>,
+}
+
+impl UserTransaction for SqliteTransaction<'_> {
+    fn get(&self, username: &str) -> Result<Option<UserAccount>, Box<dyn Error>> {
+        get_user(&self.tx, username)
+    }
+
+    fn insert(&mut self, user: &UserAccount) -> Result<(), Box<dyn Error>> {
+        insert_user(&self.tx, user)
+    }
+}
+
+impl UserStore for SqliteUserStore {
+    fn get(&self, username: &str) -> Result<Option<UserAccount>, Box<dyn Error>> {
+        get_user(&*self.conn()?, username)
+    }
+
+    fn insert(&self, user: &UserAccount) -> Result<(), Box<dyn Error>> {
+        let _write = begin_write()?;
+        let start = Instant::now();
+        insert_user(&*self.conn()?, user)?;
+        observe_db_save(start);
+        Ok(())
+    }
+
+    fn delete(&self, username: &str) -> Result<bool, Box<dyn Error>> {
+        let _write = begin_write()?;
+        let deleted = self.conn()?.execute("DELETE FROM users WHERE username = ?1", params![username])?;
+        Ok(deleted > 0)
+    }
+
+    fn list(&self) -> Result<Vec<UserAccount>, Box<dyn Error>> {
+        query_users(&*self.conn()?, "SELECT account FROM users ORDER BY username", [])
+    }
+
+    fn get_by_phone(&self, phone_number: &str) -> Result<Vec<UserAccount>, Box<dyn Error>> {
+        query_users(&*self.conn()?, "SELECT account FROM users WHERE phone_number = ?1 ORDER BY username",
+                    params![phone_number])
+    }
+
+    fn transaction(&self, task: TransactionTask) -> Result<(), Box<dyn Error>> {
+        let _write = begin_write()?;
+        let start = Instant::now();
+        let mut conn = self.conn()?;
+        let mut tx = SqliteTransaction { tx: conn.transaction()? };
+        // The transaction is rolled back when dropped
+        task(&mut tx)?;
+        tx.tx.commit()?;
+        observe_db_save(start);
+        Ok(())
+    }
+
+    // Every change is already committed to the file
+    fn flush(&self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    fn check(&self) -> Result<(), Box<dyn Error>> {
+        let result: String = self.conn()?.query_row("PRAGMA quick_check", [], |row| row.get(0))?;
+        if result != "ok" {
+            return Err(format!("The SQLite database is corrupted: {}", result).into());
+        }
+        Ok(())
+    }
+
+    fn backup(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        // VACUUM INTO copies the database as of a single transaction
+        self.conn()?.execute("VACUUM INTO ?1", params![path])?;
+        File::open(path)?.sync_all()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashing_tools::PasswordHash;
+
+    fn user(username: &str, phone_number: &str) -> UserAccount {
+        let hash = PasswordHash { hash: "hash".to_string(), pepper_version: 0 };
+        UserAccount::new(username.to_string(), hash, phone_number.to_string(), UserRole::StandardUser)
+    }
+
+    #[test]
+    fn test_sqlite_store() {
+        let store = SqliteUserStore::open(":memory:").unwrap();
+        assert!(store.is_empty().unwrap());
+
+        store.insert(&user("alice", "0791112233")).unwrap();
+        store.insert(&user("alice", "0794445566")).unwrap();
+        assert_eq!(store.list().unwrap().len(), 1);
+        assert_eq!(store.get("alice").unwrap().unwrap().phone_number(), "0794445566");
+        assert_eq!(store.get_by_phone("0794445566").unwrap().len(), 1);
+        assert!(store.get_by_phone("0791112233").unwrap().is_empty());
+
+        // The phone numbers are looked up with their index
+        let plan: String = store.conn().unwrap().query_row(
+            "EXPLAIN QUERY PLAN SELECT account FROM users WHERE phone_number = ?1 ORDER BY username",
+            ["0794445566"], |row| row.get(3)).unwrap();
+        assert!(plan.contains("users_phone_number"), "{}", plan);
+
+        // A failed transaction changes nothing
+        let res = store.transaction(&mut |users| {
+            users.insert(&user("bob", "0797778899"))?;
+            Err("Aborted".into())
+        });
+        assert!(res.is_err());
+        assert!(store.get("bob").unwrap().is_none());
+
+        // A backup is a database with the same accounts
+        let path = std::env::temp_dir().join(format!("sqlite-backup-test-{}.sqlite", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+        store.backup(path).unwrap();
+        let backup = SqliteUserStore::open(path).unwrap();
+        assert_eq!(backup.get("alice").unwrap().unwrap().phone_number(), "0794445566");
+        drop(backup);
+        std::fs::remove_file(path).unwrap();
+
+        assert!(store.delete("alice").unwrap());
+        assert!(store.check().is_ok());
+    }
+
+    #[test]
+    fn test_sqlite_migrations() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        let version: u32 = conn.pragma_query_value(None, "user_version", |row| row.get(0)).unwrap();
+        assert_eq!(version as usize, MIGRATIONS.len());
+
+        // Already migrated
+        migrate(&mut conn).unwrap();
+
+        // Made by a newer server
+        conn.pragma_update(None, "user_version", MIGRATIONS.len() as u32 + 1).unwrap();
+        assert!(migrate(&mut conn).is_err());
+    }
+}
@@ -0,0 +1,183 @@
+/// This file is used to manage the second factor of the users (TOTP and recovery codes)
+use crate::hashing_tools::{new_hash_password, verify_hash};
+use crate::time_tools::now_timestamp;
+use crate::user::TwoFactor;
+use rand::Rng;
+use rand::RngCore;
+use std::error::Error;
+use totp_rs::{Algorithm, Secret, TOTP};
+
+const TOTP_ISSUER: &str = "RESIGN";
+const TOTP_DIGITS: usize = 6;
+const TOTP_SKEW: u8 = 1;
+const TOTP_STEP: u64 = 30;
+const TOTP_SECRET_LENGTH: usize = 20;
+
+const RECOVERY_CODES_COUNT: usize = 10;
+const RECOVERY_CODE_CHARSET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+const RECOVERY_CODE_GROUP_LENGTH: usize = 5;
+
+/// Result of the verification of a second factor code
+pub enum SecondFactorCheck {
+    Totp,
+    RecoveryCode,
+    Invalid,
+}
+
+/// Generate a new random TOTP secret
+/// # Returns
+/// * `String` - The secret encoded in base32
+pub fn new_totp_secret() -> String {
+    let mut secret = [0u8; TOTP_SECRET_LENGTH];
+    rand::thread_rng().fill_bytes(&mut secret);
+    Secret::Raw(secret.to_vec()).to_encoded().to_string()
+}
+
+// The skew is applied by `check_totp_code_at` to know the time step of the code
+fn totp(secret: &str, username: &str) -> Result<TOTP, Box<dyn Error>> {
+    let secret = Secret::Encoded(secret.to_string())
+        .to_bytes()
+        .map_err(|_| "Invalid TOTP secret")?;
+    Ok(TOTP::new(Algorithm::SHA1, TOTP_DIGITS, 0, TOTP_STEP, secret,
+                 Some(TOTP_ISSUER.to_string()), username.to_string())?)
+}
+
+/// Build the otpauth URL used to add the secret in an authenticator app
+/// # Arguments
+/// * `secret` - TOTP secret encoded in base32
+/// * `username` - username of the account
+/// # Returns
+/// * `String` - The otpauth URL
+pub fn totp_url(secret: &str, username: &str) -> Result<String, Box<dyn Error>> {
+    Ok(totp(secret, username)?.get_url())
+}
+
+/// Check a TOTP code for the current time, the codes of the time steps already used are refused
+/// # Arguments
+/// * `secret` - TOTP secret encoded in base32
+/// * `code` - code given by the user
+/// * `last_step` - time step of the last code accepted, 0 if none
+/// # Returns
+/// * `Option<u64>` - The time step of the code if it is valid, none otherwise
+pub fn check_totp_code(secret: &str, code: &str, last_step: u64) -> Option<u64> {
+    check_totp_code_at(secret, code, last_step, now_timestamp())
+}
+
+fn check_totp_code_at(secret: &str, code: &str, last_step: u64, time: u64) -> Option<u64> {
+    let totp = totp(secret, "").ok()?;
+    let current = time / TOTP_STEP;
+    let skew = TOTP_SKEW as u64;
+    (current.saturating_sub(skew)..=current + skew)
+        .filter(|step| *step > last_step)
+        .find(|step| totp.check(code, step * TOTP_STEP))
+}
+
+/// Generate the TOTP code of the current time, as an authenticator app would
+#[cfg(test)]
+pub fn current_totp_code(secret: &str) -> String {
+    totp(secret, "").unwrap().generate(now_timestamp())
+}
+
+/// Generate a new set of recovery codes
+/// # Returns
+/// * `(Vec<String>, Vec<String>)` - A tuple containing the codes to show once and their hashes
+pub fn new_recovery_codes() -> (Vec<String>, Vec<String>) {
+    let mut rng = rand::thread_rng();
+    let mut codes = Vec::with_capacity(RECOVERY_CODES_COUNT);
+    let mut hashes = Vec::with_capacity(RECOVERY_CODES_COUNT);
+
+    for _ in 0..RECOVERY_CODES_COUNT {
+        // Format: xxxxx-xxxxx
+        let mut code = String::with_capacity(RECOVERY_CODE_GROUP_LENGTH * 2 + 1);
+        for i in 0..RECOVERY_CODE_GROUP_LENGTH * 2 {
+            if i == RECOVERY_CODE_GROUP_LENGTH {
+                code.push('-');
+            }
+            code.push(RECOVERY_CODE_CHARSET[rng.gen_range(0..RECOVERY_CODE_CHARSET.len())] as char);
+        }
+        hashes.push(new_hash_password(&code));
+        codes.push(code);
+    }
+
+    (codes, hashes)
+}
+
+/// Verify a second factor code, either a TOTP code or a recovery code
+/// A recovery code is removed from the account once used, and the time step of a TOTP code is kept
+/// # Arguments
+/// * `two_factor` - second factor of the user
+/// * `code` - code given by the user
+/// # Returns
+/// * `SecondFactorCheck` - Which kind of code has been accepted, if any
+pub fn verify_second_factor(two_factor: &mut TwoFactor, code: &str) -> SecondFactorCheck {
+    if let Some(step) = check_totp_code(two_factor.totp_secret(), code, two_factor.last_totp_step()) {
+        two_factor.set_last_totp_step(step);
+        return SecondFactorCheck::Totp;
+    }
+
+    let position = two_factor.recovery_codes()
+        .iter()
+        .position(|hash| verify_hash(hash, code));
+    match position {
+        Some(index) => {
+            two_factor.remove_recovery_code(index);
+            SecondFactorCheck::RecoveryCode
+        }
+        None => SecondFactorCheck::Invalid,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIME: u64 = 1_700_000_000;
+
+    fn code_at(secret: &str, time: u64) -> String {
+        totp(secret, "").unwrap().generate(time)
+    }
+
+    #[test]
+    fn test_totp_code() {
+        let secret = new_totp_secret();
+        let step = TIME / TOTP_STEP;
+        assert_eq!(check_totp_code_at(&secret, &code_at(&secret, TIME), 0, TIME), Some(step));
+
+        // The codes of the next and previous time steps are accepted for the clock drift
+        assert_eq!(check_totp_code_at(&secret, &code_at(&secret, TIME + TOTP_STEP), 0, TIME), Some(step + 1));
+        assert_eq!(check_totp_code_at(&secret, &code_at(&secret, TIME - TOTP_STEP), 0, TIME), Some(step - 1));
+        assert!(check_totp_code_at(&secret, &code_at(&secret, TIME - 2 * TOTP_STEP), 0, TIME).is_none());
+        assert!(check_totp_code_at("not base32!", &code_at(&secret, TIME), 0, TIME).is_none());
+    }
+
+    #[test]
+    fn test_totp_replay() {
+        let secret = new_totp_secret();
+        let code = code_at(&secret, TIME);
+        let step = check_totp_code_at(&secret, &code, 0, TIME).unwrap();
+        assert!(check_totp_code_at(&secret, &code, step, TIME).is_none());
+
+        // A code older than the last one accepted is refused too
+        assert!(check_totp_code_at(&secret, &code_at(&secret, TIME - TOTP_STEP), step, TIME).is_none());
+        assert_eq!(check_totp_code_at(&secret, &code_at(&secret, TIME + TOTP_STEP), step, TIME), Some(step + 1));
+    }
+
+    #[test]
+    fn test_verify_second_factor() {
+        let secret = new_totp_secret();
+        let (codes, hashes) = new_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODES_COUNT);
+        let mut two_factor = TwoFactor::new(secret.clone(), hashes, 0);
+
+        let code = current_totp_code(&secret);
+        assert!(matches!(verify_second_factor(&mut two_factor, &code), SecondFactorCheck::Totp));
+        assert!(matches!(verify_second_factor(&mut two_factor, &code), SecondFactorCheck::Invalid));
+
+        // A recovery code can be used once
+        assert!(matches!(verify_second_factor(&mut two_factor, &codes[3]), SecondFactorCheck::RecoveryCode));
+        assert_eq!(two_factor.recovery_codes().len(), RECOVERY_CODES_COUNT - 1);
+        assert!(matches!(verify_second_factor(&mut two_factor, &codes[3]), SecondFactorCheck::Invalid));
+        assert!(matches!(verify_second_factor(&mut two_factor, &codes[4]), SecondFactorCheck::RecoveryCode));
+        assert!(matches!(verify_second_factor(&mut two_factor, "aaaaa-aaaaa"), SecondFactorCheck::Invalid));
+    }
+}
@@ -1,6 +1,8 @@
 /// This file is used to store and retrieve user accounts from the database
 ///
 /// Tasks: - Potential improvements
+use crate::hashing_tools::PasswordHash;
+use crate::time_tools::now_timestamp;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -13,9 +15,43 @@ pub enum UserRole {
 pub struct UserAccount {
     username: String,
     hash_password: String,
-    salt: [u8;16],
+    // Hashes made before the peppers have no pepper
+    #[serde(default)]
+    pepper_version: u32,
     phone_number: String,
     role: UserRole,
+    #[serde(default)]
+    two_factor: Option<TwoFactor>,
+    #[serde(default)]
+    phone_verified: bool,
+    #[serde(default)]
+    pending_phone: Option<PendingPhone>,
+    // Previous password hashes, the most recent first
+    #[serde(default)]
+    password_history: Vec<PasswordHash>,
+    // Accounts created before this field start their password lifetime when loaded
+    #[serde(default = "now_timestamp")]
+    password_changed_at: u64,
+}
+
+/// Second factor of an user account
+/// The recovery codes are stored only as argon2 hashes and are single-use
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TwoFactor {
+    totp_secret: String,
+    recovery_codes: Vec<String>,
+    // Time step of the last TOTP code accepted, a code can't be used twice
+    #[serde(default)]
+    last_totp_step: u64,
+}
+
+/// Phone number waiting for the confirmation of the PIN sent by SMS
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PendingPhone {
+    phone_number: String,
+    pin_hash: String,
+    expires_at: u64,
+    attempts_left: u8,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -25,13 +61,18 @@ pub struct UserAccountPublic {
 }
 
 impl UserAccount {
-    pub fn new(username: String, hash_password: String, salt: [u8;16], phone_number: String, role: UserRole) -> Self {
+    pub fn new(username: String, password_hash: PasswordHash, phone_number: String, role: UserRole) -> Self {
         Self {
             username,
-            hash_password,
-            salt,
+            hash_password: password_hash.hash,
+            pepper_version: password_hash.pepper_version,
             phone_number,
             role,
+            two_factor: None,
+            phone_verified: false,
+            pending_phone: None,
+            password_history: Vec::new(),
+            password_changed_at: now_timestamp(),
         }
     }
 
@@ -39,12 +80,37 @@ impl UserAccount {
         &self.username
     }
 
-    pub fn hash_password(&self) -> &str {
-        &self.hash_password
+    pub fn password_hash(&self) -> PasswordHash {
+        PasswordHash {
+            hash: self.hash_password.clone(),
+            pepper_version: self.pepper_version,
+        }
     }
 
-    pub fn salt(&self) -> &[u8;16] {
-        &self.salt
+    /// Replace the password, the previous hash is kept in the history
+    /// # Arguments
+    /// * `password_hash` - hash of the new password
+    /// * `history_size` - maximum number of previous hashes to keep
+    pub fn set_password(&mut self, password_hash: PasswordHash, history_size: usize) {
+        let previous = self.password_hash();
+        self.set_password_hash(password_hash);
+        self.password_history.insert(0, previous);
+        self.password_history.truncate(history_size);
+        self.password_changed_at = now_timestamp();
+    }
+
+    /// Replace the hash of the same password, e.g. with new hashing parameters or pepper
+    pub fn set_password_hash(&mut self, password_hash: PasswordHash) {
+        self.hash_password = password_hash.hash;
+        self.pepper_version = password_hash.pepper_version;
+    }
+
+    pub fn password_history(&self) -> &[PasswordHash] {
+        &self.password_history
+    }
+
+    pub fn password_changed_at(&self) -> u64 {
+        self.password_changed_at
     }
 
     pub fn role(&self) -> &UserRole {
@@ -55,7 +121,107 @@ impl UserAccount {
         &self.phone_number
     }
 
+    /// Set a phone number that hasn't been verified by the user
     pub fn set_phone_number(&mut self, phone_number: String) {
         self.phone_number = phone_number;
+        self.phone_verified = false;
+    }
+
+    pub fn set_verified_phone_number(&mut self, phone_number: String) {
+        self.phone_number = phone_number;
+        self.phone_verified = true;
+    }
+
+    pub fn phone_verified(&self) -> bool {
+        self.phone_verified
+    }
+
+    pub fn pending_phone(&self) -> Option<&PendingPhone> {
+        self.pending_phone.as_ref()
+    }
+
+    pub fn pending_phone_mut(&mut self) -> Option<&mut PendingPhone> {
+        self.pending_phone.as_mut()
+    }
+
+    pub fn set_pending_phone(&mut self, pending_phone: Option<PendingPhone>) {
+        self.pending_phone = pending_phone;
+    }
+
+    pub fn two_factor(&self) -> Option<&TwoFactor> {
+        self.two_factor.as_ref()
+    }
+
+    pub fn two_factor_mut(&mut self) -> Option<&mut TwoFactor> {
+        self.two_factor.as_mut()
+    }
+
+    pub fn set_two_factor(&mut self, two_factor: Option<TwoFactor>) {
+        self.two_factor = two_factor;
+    }
+}
+
+impl TwoFactor {
+    pub fn new(totp_secret: String, recovery_codes: Vec<String>, last_totp_step: u64) -> Self {
+        Self {
+            totp_secret,
+            recovery_codes,
+            last_totp_step,
+        }
+    }
+
+    pub fn totp_secret(&self) -> &str {
+        &self.totp_secret
+    }
+
+    pub fn recovery_codes(&self) -> &[String] {
+        &self.recovery_codes
+    }
+
+    pub fn set_recovery_codes(&mut self, recovery_codes: Vec<String>) {
+        self.recovery_codes = recovery_codes;
+    }
+
+    pub fn remove_recovery_code(&mut self, index: usize) {
+        self.recovery_codes.remove(index);
+    }
+
+    pub fn last_totp_step(&self) -> u64 {
+        self.last_totp_step
+    }
+
+    pub fn set_last_totp_step(&mut self, last_totp_step: u64) {
+        self.last_totp_step = last_totp_step;
+    }
+}
+
+impl PendingPhone {
+    pub fn new(phone_number: String, pin_hash: String, expires_at: u64, attempts_left: u8) -> Self {
+        Self {
+            phone_number,
+            pin_hash,
+            expires_at,
+            attempts_left,
+        }
+    }
+
+    pub fn phone_number(&self) -> &str {
+        &self.phone_number
+    }
+
+    pub fn pin_hash(&self) -> &str {
+        &self.pin_hash
+    }
+
+    pub fn expires_at(&self) -> u64 {
+        self.expires_at
+    }
+
+    pub fn attempts_left(&self) -> u8 {
+        self.attempts_left
+    }
+
+    pub fn decrease_attempts(&mut self) {
+        self.attempts_left = self.attempts_left.saturating_sub(1);
     }
 }
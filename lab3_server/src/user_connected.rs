@@ -1,44 +1,94 @@
 use crate::Connection;
-use crate::database::Database;
+use crate::user_store::UserStore;
 use crate::user::UserAccount;
+use crate::session_tokens::{issue_session_token, revoke_session_token};
+use crate::session_registry::{register_session, set_session_user, touch_session, unregister_session};
 use std::error::Error;
 
 /// Used to represent a connected user for the actions
+/// The connection is registered in the session registry while the user exists
 pub struct ConnectedUser {
     username: Option<String>,
+    session_token: Option<String>,
+    session_id: u64,
+    peer_ip: String,
     pub conn: Connection,
 }
 
 impl ConnectedUser {
-    pub fn anonymous(conn: Connection) -> ConnectedUser {
-        ConnectedUser {
+    pub fn anonymous(conn: Connection) -> Result<ConnectedUser, Box<dyn Error>> {
+        let peer_ip = conn.peer_addr()?.ip().to_string();
+        let session_id = register_session(peer_ip.clone(), conn.try_clone_socket()?);
+        Ok(ConnectedUser {
             username: None,
+            session_token: None,
+            session_id,
+            peer_ip,
             conn,
-        }
+        })
     }
 
     pub fn username(&mut self) -> String {
         self.username.as_ref().unwrap().clone()
     }
 
+    pub fn logged_username(&self) -> Option<&str> {
+        self.username.as_deref()
+    }
+
+    pub fn peer_ip(&self) -> &str {
+        &self.peer_ip
+    }
+
     pub fn conn(&mut self) -> &mut Connection {
         &mut self.conn
     }
 
-    pub fn set_username(&mut self, username: &str) {
+    /// Login the user and issue him a new session token
+    /// # Returns
+    /// * `String` - The session token to give to the client
+    pub fn login(&mut self, username: &str) -> String {
         self.username = Some(username.to_string());
+        let token = issue_session_token(username);
+        self.session_token = Some(token.clone());
+        set_session_user(self.session_id, Some(username), Some(&token));
+        token
+    }
+
+    pub fn session_token(&self) -> Option<&str> {
+        self.session_token.as_deref()
+    }
+
+    pub fn session_id(&self) -> u64 {
+        self.session_id
+    }
+
+    /// Update the last activity of the session
+    pub fn touch(&self) {
+        touch_session(self.session_id);
     }
 
     pub fn is_anonymous(&self) -> bool {
         return self.username.is_none();
     }
 
+    /// Logout the user and revoke his session token
     pub fn logout(&mut self) {
         self.username = None;
+        if let Some(token) = self.session_token.take() {
+            revoke_session_token(&token);
+        }
+        set_session_user(self.session_id, None, None);
     }
 
-    pub fn user_account(&mut self) -> Result<UserAccount, Box<dyn Error>> {
+    pub fn user_account(&mut self, store: &dyn UserStore) -> Result<UserAccount, Box<dyn Error>> {
         // No log cause the server crashes if it doesn't work
-        Ok(Database::get(&self.username())?.expect("User logged in but not in DB"))
+        Ok(store.get(&self.username())?.expect("User logged in but not in DB"))
+    }
+}
+
+impl Drop for ConnectedUser {
+    fn drop(&mut self) {
+        unregister_session(self.session_id);
     }
 }
\ No newline at end of file
@@ -0,0 +1,249 @@
+/// This file is used to abstract the storage of the user accounts
+/// The actions use a `UserStore` given by the server, the RON file or SQLite store chosen in the config
+/// and the in-memory store in the tests
+use crate::database::{default_accounts, RonUserStore, DB_PATH};
+use crate::env_reader::CONFIG;
+use crate::sqlite_store::SqliteUserStore;
+use crate::user::UserAccount;
+use std::collections::HashMap;
+use std::error::Error;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+
+const WRITES_CLOSED: &str = "The server is shutting down, the accounts can't be changed anymore";
+
+// Held for reading by each change of the accounts, and for writing to stop the changes before the shutdown
+static WRITES_OPEN: RwLock<bool> = RwLock::new(true);
+
+/// User accounts by username
+pub type Users = HashMap<String, UserAccount>;
+
+/// Task of a transaction, the changes are kept only if it succeeds
+pub type TransactionTask<'a> = &'a mut dyn FnMut(&mut dyn UserTransaction) -> Result<(), Box<dyn Error>>;
+
+/// Accounts seen and changed by a transaction
+pub trait UserTransaction {
+    fn get(&self, username: &str) -> Result<Option<UserAccount>, Box<dyn Error>>;
+
+    fn insert(&mut self, user: &UserAccount) -> Result<(), Box<dyn Error>>;
+}
+
+// The stores keeping every account in memory run the transactions on a copy of the accounts
+impl UserTransaction for Users {
+    fn get(&self, username: &str) -> Result<Option<UserAccount>, Box<dyn Error>> {
+        Ok(HashMap::get(self, username).cloned())
+    }
+
+    fn insert(&mut self, user: &UserAccount) -> Result<(), Box<dyn Error>> {
+        HashMap::insert(self, user.username().to_string(), user.clone());
+        Ok(())
+    }
+}
+
+/// Storage of the user accounts, shared by the threads of the server
+pub trait UserStore: Send + Sync {
+    /// Get an account by username
+    fn get(&self, username: &str) -> Result<Option<UserAccount>, Box<dyn Error>>;
+
+    /// Insert or replace an account
+    fn insert(&self, user: &UserAccount) -> Result<(), Box<dyn Error>>;
+
+    /// Delete an account
+    /// # Returns
+    /// * `Result<bool, Box<dyn Error>>` - True if the account existed
+    // No action deletes accounts yet
+    #[allow(dead_code)]
+    fn delete(&self, username: &str) -> Result<bool, Box<dyn Error>>;
+
+    /// List every account
+    fn list(&self) -> Result<Vec<UserAccount>, Box<dyn Error>>;
+
+    /// Get the accounts with a phone number, looked up in the index of the phone numbers if the store has one
+    fn get_by_phone(&self, phone_number: &str) -> Result<Vec<UserAccount>, Box<dyn Error>>;
+
+    /// Apply changes to the accounts atomically, no other change happens during the task
+    /// and nothing is changed if it fails
+    fn transaction(&self, task: TransactionTask) -> Result<(), Box<dyn Error>>;
+
+    /// Write the pending changes to the storage, e.g. before stopping the server
+    fn flush(&self) -> Result<(), Box<dyn Error>>;
+
+    /// Check that the storage is usable
+    fn check(&self) -> Result<(), Box<dyn Error>>;
+
+    /// Write a consistent copy of the storage, e.g. for a backup
+    /// # Arguments
+    /// * `path` - path of the copy, which must not exist
+    fn backup(&self, path: &str) -> Result<(), Box<dyn Error>>;
+}
+
+/// The stores that can be selected in the config
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum UserStoreKind {
+    Ron,
+    Sqlite,
+}
+
+impl FromStr for UserStoreKind {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "ron" => Ok(UserStoreKind::Ron),
+            "sqlite" => Ok(UserStoreKind::Sqlite),
+            _ => Err(format!("Invalid user store: {}", s).into()),
+        }
+    }
+}
+
+/// Start a change of the accounts, the stores call it before changing anything
+/// # Returns
+/// * `Result<RwLockReadGuard<bool>, Box<dyn Error>>` - The guard to keep until the change is done,
+///   an error if the changes are stopped
+pub fn begin_write() -> Result<RwLockReadGuard<'static, bool>, Box<dyn Error>> {
+    // A panic during a change doesn't poison the lock, only `close_writes` takes it for writing
+    let open = WRITES_OPEN.read().unwrap_or_else(|e| e.into_inner());
+    if !*open {
+        return Err(WRITES_CLOSED.into());
+    }
+    Ok(open)
+}
+
+/// Wait for the changes of the accounts in progress and refuse the next ones, e.g. before the shutdown
+pub fn close_writes() {
+    *WRITES_OPEN.write().unwrap_or_else(|e| e.into_inner()) = false;
+}
+
+/// Open the store selected in the config, a new store gets the default accounts
+/// # Returns
+/// * `Result<Arc<dyn UserStore>, Box<dyn Error>>` - The store, shared by the threads of the server
+pub fn open_user_store() -> Result<Arc<dyn UserStore>, Box<dyn Error>> {
+    let store: Arc<dyn UserStore> = match CONFIG.user_store {
+        UserStoreKind::Ron => Arc::new(RonUserStore::open(DB_PATH)?),
+        UserStoreKind::Sqlite => {
+            let store = SqliteUserStore::open(&CONFIG.sqlite_path)?;
+            if store.is_empty()? {
+                store.transaction(&mut |users| {
+                    default_accounts().iter().try_for_each(|user| users.insert(user))
+                })?;
+            }
+            Arc::new(store)
+        }
+    };
+    Ok(store)
+}
+
+#[cfg(test)]
+const POISONED_STORE: &str = "The user store is unusable after a panic";
+
+/// Store keeping the accounts in memory only
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemoryUserStore {
+    users: RwLock<Users>,
+}
+
+#[cfg(test)]
+impl MemoryUserStore {
+    pub fn new(users: Vec<UserAccount>) -> MemoryUserStore {
+        let users = users.into_iter().map(|user| (user.username().to_string(), user)).collect();
+        MemoryUserStore { users: RwLock::new(users) }
+    }
+}
+
+#[cfg(test)]
+impl UserStore for MemoryUserStore {
+    fn get(&self, username: &str) -> Result<Option<UserAccount>, Box<dyn Error>> {
+        Ok(self.users.read().map_err(|_| POISONED_STORE)?.get(username).cloned())
+    }
+
+    fn insert(&self, user: &UserAccount) -> Result<(), Box<dyn Error>> {
+        self.users.write().map_err(|_| POISONED_STORE)?.insert(user.username().to_string(), user.clone());
+        Ok(())
+    }
+
+    fn delete(&self, username: &str) -> Result<bool, Box<dyn Error>> {
+        Ok(self.users.write().map_err(|_| POISONED_STORE)?.remove(username).is_some())
+    }
+
+    fn list(&self) -> Result<Vec<UserAccount>, Box<dyn Error>> {
+        Ok(self.users.read().map_err(|_| POISONED_STORE)?.values().cloned().collect())
+    }
+
+    fn get_by_phone(&self, phone_number: &str) -> Result<Vec<UserAccount>, Box<dyn Error>> {
+        let users = self.users.read().map_err(|_| POISONED_STORE)?;
+        Ok(users.values().filter(|user| user.phone_number() == phone_number).cloned().collect())
+    }
+
+    fn transaction(&self, task: TransactionTask) -> Result<(), Box<dyn Error>> {
+        let mut users = self.users.write().map_err(|_| POISONED_STORE)?;
+        let mut changed = users.clone();
+        task(&mut changed)?;
+        *users = changed;
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    fn check(&self) -> Result<(), Box<dyn Error>> {
+        if self.users.is_poisoned() {
+            return Err(POISONED_STORE.into());
+        }
+        Ok(())
+    }
+
+    fn backup(&self, _path: &str) -> Result<(), Box<dyn Error>> {
+        Err("The in-memory store has no file to back up".into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashing_tools::PasswordHash;
+    use crate::user::UserRole;
+
+    fn user(username: &str, phone_number: &str) -> UserAccount {
+        let hash = PasswordHash { hash: "hash".to_string(), pepper_version: 0 };
+        UserAccount::new(username.to_string(), hash, phone_number.to_string(), UserRole::StandardUser)
+    }
+
+    #[test]
+    fn test_memory_store() {
+        let store = MemoryUserStore::new(vec![user("alice", "0791112233")]);
+        store.insert(&user("bob", "0794445566")).unwrap();
+
+        assert_eq!(store.get("alice").unwrap().unwrap().phone_number(), "0791112233");
+        assert!(store.get("carol").unwrap().is_none());
+        assert_eq!(store.list().unwrap().len(), 2);
+
+        let bob = store.get_by_phone("0794445566").unwrap();
+        assert_eq!(bob.len(), 1);
+        assert_eq!(bob[0].username(), "bob");
+        assert!(store.get_by_phone("0797778899").unwrap().is_empty());
+
+        assert!(store.delete("bob").unwrap());
+        assert!(!store.delete("bob").unwrap());
+        assert!(store.check().is_ok());
+    }
+
+    #[test]
+    fn test_memory_store_transaction() {
+        let store = MemoryUserStore::new(vec![user("alice", "0791112233")]);
+
+        store.transaction(&mut |users| {
+            users.insert(&user("bob", "0794445566"))
+        }).unwrap();
+        assert!(store.get("bob").unwrap().is_some());
+
+        // A failed transaction changes nothing
+        let res = store.transaction(&mut |users| {
+            users.insert(&user("alice", "0797778899"))?;
+            Err("Aborted".into())
+        });
+        assert!(res.is_err());
+        assert_eq!(store.get("alice").unwrap().unwrap().phone_number(), "0791112233");
+    }
+}